@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use ropey::{Rope, RopeSlice};
+
+// Document text stored as a rope, lines are separated by a single '\n' and
+// every position is a (line index, char index within that line) pair.
+#[derive(Debug, Clone, Default)]
+pub struct TextBuffer {
+    rope: Rope,
+}
+
+impl TextBuffer {
+    pub fn from_lines<'a, I>(lines: I) -> Self
+        where I: IntoIterator<Item = &'a str>
+    {
+        let mut builder = ropey::RopeBuilder::new();
+        for (i, line) in lines.into_iter().enumerate() {
+            if i > 0 {builder.append("\n");}
+            builder.append(line);
+        }
+        Self { rope: builder.finish() }
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    fn line_slice(&self, index: usize) -> Option<RopeSlice<'_>> {
+        if index >= self.len_lines() {
+            return None;
+        }
+        let line = self.rope.line(index);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' {
+            Some(line.slice(..len - 1))
+        } else {
+            Some(line)
+        }
+    }
+
+    pub fn line(&self, index: usize) -> Option<Cow<'_, str>> {
+        self.line_slice(index).map(|l| l.into())
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        (0..self.len_lines()).map(|i| self.line(i).unwrap())
+    }
+
+    fn char_offset(&self, line_index: usize, char_index: usize) -> usize {
+        self.rope.line_to_char(line_index) + char_index
+    }
+
+    // Byte offset of a position, as incremental parsers count.
+    pub fn byte_offset(&self, at: (usize, usize)) -> usize {
+        self.rope.char_to_byte(self.char_offset(at.0, at.1))
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.rope.len_bytes()
+    }
+
+    // Text from `byte` to the end of the rope chunk holding it, for parsers
+    // that read the text piece by piece.
+    pub fn chunk_at_byte(&self, byte: usize) -> &str {
+        if byte >= self.rope.len_bytes() {
+            return "";
+        }
+        let (chunk, chunk_start, _, _) = self.rope.chunk_at_byte(byte);
+        &chunk[byte - chunk_start..]
+    }
+
+    // Offsets past the end or inside a char, as a tree edited but not parsed
+    // again can have, are taken back to the start of the char.
+    pub fn text_in_bytes(&self, range: std::ops::Range<usize>) -> Cow<'_, str> {
+        let at = |byte: usize| self.rope.char_to_byte(self.rope.byte_to_char(byte.min(self.rope.len_bytes())));
+        let start = at(range.start);
+        self.rope.byte_slice(start..at(range.end).max(start)).into()
+    }
+
+    pub fn insert(&mut self, at: (usize, usize), text: &str) -> (usize, usize) {
+        let offset = self.char_offset(at.0, at.1);
+        self.rope.insert(offset, text);
+        Self::end_of(at, text)
+    }
+
+    pub fn remove(&mut self, from: (usize, usize), to: (usize, usize)) -> String {
+        let start = self.char_offset(from.0, from.1);
+        let end = self.char_offset(to.0, to.1);
+        let removed = self.rope.slice(start..end).to_string();
+        self.rope.remove(start..end);
+        removed
+    }
+
+    pub fn text_between(&self, from: (usize, usize), to: (usize, usize)) -> String {
+        let start = self.char_offset(from.0, from.1);
+        let end = self.char_offset(to.0, to.1);
+        self.rope.slice(start..end).to_string()
+    }
+
+    // Position just past `text` if it were inserted at `at`.
+    pub fn end_of(at: (usize, usize), text: &str) -> (usize, usize) {
+        match text.rfind('\n') {
+            Some(last_newline) => (
+                at.0 + text.matches('\n').count(),
+                text[last_newline + 1..].chars().count(),
+            ),
+            None => (at.0, at.1 + text.chars().count()),
+        }
+    }
+
+    pub fn write_to<W>(&self, out: &mut W, line_ending: &str) -> std::io::Result<()>
+        where W: Write
+    {
+        for (i, line) in self.lines().enumerate() {
+            if i > 0 {out.write_all(line_ending.as_bytes())?;}
+            out.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use super::Editor;
+use super::history::EditKind;
+use super::buffer::TextBuffer;
+
+pub const DEFAULT_REGISTER: char = '"';
+
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub text: String,
+    // Yanked as whole lines, `text` then ends in a newline.
+    pub linewise: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Clipboard {
+    registers: HashMap<char, Register>,
+    // Also hand copies to the terminal through OSC 52, which reaches the
+    // local system clipboard even over ssh.
+    pub use_osc52: bool,
+    pending_osc52: Option<String>,
+}
+
+impl Clipboard {
+    pub fn get(&self, register: char) -> Option<&Register> {
+        self.registers.get(&register)
+    }
+
+    pub fn set(&mut self, register: char, text: String, linewise: bool) {
+        if self.use_osc52 && register == DEFAULT_REGISTER {
+            self.pending_osc52 = Some(osc52_sequence(&text));
+        }
+        self.registers.insert(register, Register { text, linewise });
+    }
+
+    // Escape sequence the renderer still has to write out, if any.
+    pub fn take_pending_osc52(&mut self) -> Option<String> {
+        self.pending_osc52.take()
+    }
+}
+
+fn osc52_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+impl Editor {
+
+    pub (super) fn copy_selection(&mut self) -> bool {
+        match self.selected_text() {
+            Some(text) => {
+                self.clipboard.set(DEFAULT_REGISTER, text, false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub (super) fn cut_selection(&mut self) -> bool {
+        self.copy_selection() && self.delete_selection()
+    }
+
+    // Inserts `text` at the cursor, replacing the selection, as one undo step
+    // and leaves the cursor after it.
+    pub (super) fn put_text_on_cursor(&mut self, text: &str) {
+        let cursor = self.cursor_char_position();
+        let (from, to) = self.selection_char_range().unwrap_or((cursor, cursor));
+        self.clear_selection();
+        self.replace_text(from, to, text, EditKind::Other);
+        self.set_cursor_position(self.position_at_char(TextBuffer::end_of(from, text)).unwrap());
+    }
+
+    pub (super) fn put_pasted_text(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.put_text_on_cursor(&text);
+    }
+
+    pub (super) fn paste(&mut self, register: char) -> bool {
+        match self.clipboard.get(register).cloned() {
+            Some(Register { text, .. }) => {
+                self.put_text_on_cursor(&text);
+                true
+            }
+            None => false,
+        }
+    }
+
+}
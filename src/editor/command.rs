@@ -0,0 +1,462 @@
+use std::path::Path;
+
+use crossterm::event;
+
+use super::Editor;
+use super::EditorMode;
+use super::replace::{self, ReplaceRange};
+use super::layout::Direction;
+use super::highlight;
+use super::document::LineEnding;
+use super::position::Position;
+
+const COMMAND_NAMES: &[&str] = &["b", "bd", "bdelete", "bn", "bnext", "bp", "bprevious", "buffer", "buffers", "close", "e", "edit", "goto", "ls", "noh", "nohlsearch", "only", "q", "quit", "s", "set", "split", "substitute", "theme", "vsplit", "w", "wq", "write", "x"];
+const OPTION_NAMES: &[&str] = &["backup", "backupdir", "expandtab", "fileformat", "followcursor", "ignorecase", "linebreak", "osc52", "readonly", "regex", "showcursor", "tabstop", "themebg", "treesitter", "wrap"];
+
+// State of the `:` prompt at the bottom of the screen.
+#[derive(Debug, Default)]
+pub struct CommandLine {
+    pub input: String,
+    pub cursor: usize,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    // Candidates for the word being completed and the one currently shown.
+    completion: Option<(Vec<String>, usize)>,
+    // Selection the prompt was opened with, what `'<,'>` refers to.
+    pub selection: Option<((usize, usize), (usize, usize))>,
+    return_mode: Option<EditorMode>,
+}
+
+impl CommandLine {
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.input.char_indices().nth(char_index).map_or(self.input.len(), |(i, _)| i)
+    }
+
+    pub fn insert_str(&mut self, text: &str) {
+        let index = self.byte_index(self.cursor);
+        self.input.insert_str(index, text);
+        self.cursor += text.chars().count();
+    }
+
+    fn set_input(&mut self, input: String) {
+        self.cursor = input.chars().count();
+        self.input = input;
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) => self.history.len().checked_sub(1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (_, false) => None,
+        };
+        self.history_index = index;
+        self.set_input(index.map_or(String::new(), |i| self.history[i].clone()));
+    }
+
+    // Line editing and history keys shared by every prompt, returns false for
+    // keys it does not handle.
+    pub fn edit(&mut self, code: event::KeyCode) -> bool {
+        match code {
+            event::KeyCode::Up => self.browse_history(true),
+            event::KeyCode::Down => self.browse_history(false),
+            event::KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            event::KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.chars().count()),
+            event::KeyCode::Home => self.cursor = 0,
+            event::KeyCode::End => self.cursor = self.input.chars().count(),
+            event::KeyCode::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    let index = self.byte_index(self.cursor);
+                    self.input.remove(index);
+                }
+            }
+            event::KeyCode::Char(c) => self.insert_str(c.encode_utf8(&mut [0; 4])),
+            _ => return false,
+        }
+        true
+    }
+
+    // Starts a fresh, empty input.
+    pub fn reset(&mut self) {
+        self.set_input(String::new());
+        self.history_index = None;
+        self.completion = None;
+    }
+
+    // Takes the entered text, remembering it in the history.
+    pub fn submit(&mut self) -> String {
+        let input = std::mem::take(&mut self.input);
+        if !input.trim().is_empty() && self.history.last() != Some(&input) {
+            self.history.push(input.clone());
+        }
+        input
+    }
+
+    fn word_start(&self) -> usize {
+        self.input.rfind(' ').map_or(0, |i| i + 1)
+    }
+
+    // Command name and the partial word being completed.
+    fn completion_context(&self) -> (String, String) {
+        let word_start = self.word_start();
+        let command = self.input[..word_start].split_whitespace().next().unwrap_or("");
+        (command.to_string(), self.input[word_start..].to_string())
+    }
+
+    fn show_completion(&mut self) {
+        if let Some((candidates, shown)) = &self.completion {
+            let candidate = candidates[*shown].clone();
+            self.input.truncate(self.word_start());
+            self.input.push_str(&candidate);
+            self.cursor = self.input.chars().count();
+        }
+    }
+
+    // Fills in the first candidate, or the next one on repeated presses.
+    fn complete(&mut self, candidates: Vec<String>) {
+        match &mut self.completion {
+            Some((candidates, shown)) => *shown = (*shown + 1) % candidates.len(),
+            None if !candidates.is_empty() => self.completion = Some((candidates, 0)),
+            None => return,
+        }
+        self.show_completion();
+    }
+}
+
+fn file_candidates(prefix: &str) -> Vec<String> {
+    let (dir, file_prefix) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+    let entries = match std::fs::read_dir(if dir.is_empty() {Path::new(".")} else {Path::new(dir)}) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(file_prefix) || (name.starts_with('.') && !file_prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.file_type().ok()?.is_dir() {"/"} else {""};
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+fn parse_bool_option(name: &str) -> (&str, bool) {
+    match name.strip_prefix("no") {
+        Some(stripped) if OPTION_NAMES.contains(&stripped) => (stripped, false),
+        _ => (name, true),
+    }
+}
+
+impl Editor {
+
+    // Opens the prompt with `input` typed in, prefixed by the `'<,'>` range
+    // when there is a selection.
+    pub (super) fn enter_command_mode(&mut self, input: &str) {
+        self.command_line.reset();
+        self.command_line.selection = self.selection_char_range();
+        let range = if self.command_line.selection.is_some() {"'<,'>"} else {""};
+        self.command_line.set_input(format!("{}{}", range, input));
+        self.command_line.return_mode = Some(if self.mode == EditorMode::Visual {EditorMode::Normal} else {self.mode});
+        self.clear_selection();
+        self.mode = EditorMode::Command;
+    }
+
+    fn leave_command_mode(&mut self) {
+        self.mode = self.command_line.return_mode.take().unwrap_or(EditorMode::Normal);
+    }
+
+    fn completion_candidates(&self, command: &str, word: &str) -> Vec<String> {
+        let names: Vec<String> = match command {
+            "" => COMMAND_NAMES.iter().map(|name| name.to_string()).collect(),
+            "set" => OPTION_NAMES.iter().map(|name| name.to_string()).collect(),
+            "theme" => self.theme_set.themes.keys().cloned().collect(),
+            "e" | "edit" | "w" | "write" | "wq" | "x" | "sp" | "split" | "vs" | "vsplit" => return file_candidates(word),
+            "b" | "buffer" | "bd" | "bdelete" => (0..self.document_count()).map(|i| self.document(i).path.clone()).collect(),
+            _ => Vec::new(),
+        };
+        names.into_iter().filter(|name| name.starts_with(word)).collect()
+    }
+
+    pub (super) fn process_command_key(&mut self, key_event: event::KeyEvent) -> Result<bool, Box<dyn std::error::Error>> {
+        let completion = self.command_line.completion.take();
+        match key_event.code {
+            event::KeyCode::Esc => {
+                self.leave_command_mode();
+            }
+            event::KeyCode::Enter => {
+                let input = self.command_line.submit();
+                self.leave_command_mode();
+                if !input.trim().is_empty() {
+                    match self.execute_command(&input) {
+                        Ok(keep_running) => return Ok(keep_running),
+                        Err(e) => self.header_msg = e,
+                    }
+                }
+            }
+            event::KeyCode::Tab => {
+                self.command_line.completion = completion;
+                let candidates = if self.command_line.completion.is_none() {
+                    let (command, word) = self.command_line.completion_context();
+                    self.completion_candidates(&command, &word)
+                } else {
+                    Vec::new()
+                };
+                self.command_line.complete(candidates);
+            }
+            event::KeyCode::Backspace if self.command_line.input.is_empty() => {
+                self.leave_command_mode();
+            }
+            _ => {
+                self.command_line.edit(key_event.code);
+            }
+        }
+        Ok(true)
+    }
+
+    // Runs an ex-style command, returns whether the editor keeps running.
+    pub (super) fn execute_command(&mut self, input: &str) -> Result<bool, String> {
+        let input = input.trim().trim_start_matches(':');
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (input, ""),
+        };
+        let (name, force) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+
+        let (range, command) = match input.strip_prefix('%') {
+            Some(command) => (Some(ReplaceRange::All), command),
+            None => match input.strip_prefix("'<,'>") {
+                Some(command) => (Some(ReplaceRange::Selection), command),
+                None => (None, input),
+            },
+        };
+        if let Some((pattern, replacement, flags)) = replace::split_substitute(command) {
+            self.substitute(range.unwrap_or(ReplaceRange::Line), &pattern, &replacement, &flags)?;
+            return Ok(true);
+        }
+        if range.is_some() {
+            return Err(format!("Not a command that takes a range: {}", command));
+        }
+
+        if let Ok(line) = name.parse::<usize>() {
+            self.goto_line(line);
+            return Ok(true);
+        }
+        match name {
+            "w" | "write" => {
+                self.write_to(argument)?;
+            }
+            "q" | "quit" | "clo" | "close" if self.window_count() > 1 => {
+                self.close_window()?;
+            }
+            "clo" | "close" => {
+                return Err("Cannot close the last window".to_string());
+            }
+            "on" | "only" => {
+                self.only_window();
+            }
+            "sp" | "split" | "vs" | "vsplit" => {
+                let direction = if name.starts_with('v') {Direction::Vertical} else {Direction::Horizontal};
+                let path = if argument.is_empty() {None} else {Some(argument.to_string())};
+                self.split_window(direction, path);
+            }
+            "q" | "quit" => {
+                self.check_unsaved(force)?;
+                self.store_all_histories();
+                return Ok(false);
+            }
+            "wq" | "x" => {
+                if name == "wq" || !self.doc.current {
+                    self.write_to(argument)?;
+                }
+                self.check_unsaved(force)?;
+                self.store_all_histories();
+                return Ok(false);
+            }
+            "e" | "edit" if argument.is_empty() => {
+                if !self.doc.current && !force {
+                    return Err("No write since last change (add ! to override)".to_string());
+                }
+                self.reload();
+            }
+            "e" | "edit" => {
+                self.open(argument.to_string());
+            }
+            "bn" | "bnext" => {
+                self.cycle_document(true);
+            }
+            "bp" | "bprevious" => {
+                self.cycle_document(false);
+            }
+            "ls" | "buffers" => {
+                self.header_msg = self.describe_documents();
+            }
+            "b" | "buffer" => {
+                let index = self.find_document(argument)?;
+                self.switch_document(index);
+            }
+            "bd" | "bdelete" => {
+                if !argument.is_empty() {
+                    let index = self.find_document(argument)?;
+                    self.switch_document(index);
+                }
+                if !self.doc.current && !force {
+                    return Err("No write since last change (add ! to override)".to_string());
+                }
+                self.close_document();
+            }
+            "set" => {
+                if argument.is_empty() {
+                    self.header_msg = self.describe_options();
+                }
+                for option in argument.split_whitespace() {
+                    self.set_option(option)?;
+                }
+            }
+            "theme" => {
+                if !self.theme_set.themes.contains_key(argument) {
+                    return Err(format!("Unknown theme: {}", argument));
+                }
+                self.theme_name = argument.to_string();
+            }
+            "noh" | "nohlsearch" => {
+                self.search.highlight = false;
+            }
+            "goto" => {
+                let line = argument.parse::<usize>().map_err(|_| format!("Not a line number: {}", argument))?;
+                self.goto_line(line);
+            }
+            _ => return Err(format!("Not an editor command: {}", name)),
+        }
+        Ok(true)
+    }
+
+    fn check_unsaved(&self, force: bool) -> Result<(), String> {
+        match self.unsaved_document() {
+            Some(index) if !force => Err(format!(
+                "No write since last change for buffer {} (add ! to override)", index + 1)),
+            _ => Ok(()),
+        }
+    }
+
+    // Document by number as listed by `:ls`, or by a unique part of its path.
+    fn find_document(&self, argument: &str) -> Result<usize, String> {
+        if let Ok(number) = argument.parse::<usize>() {
+            return match number {
+                1.. if number <= self.document_count() => Ok(number - 1),
+                _ => Err(format!("No buffer {}", number)),
+            };
+        }
+        let matching: Vec<usize> = (0..self.document_count())
+            .filter(|i| self.document(*i).path.contains(argument))
+            .collect();
+        match matching[..] {
+            [index] => Ok(index),
+            [] => Err(format!("No matching buffer for {}", argument)),
+            _ => Err(format!("More than one match for {}", argument)),
+        }
+    }
+
+    // `:w file` writes a copy and the buffer stays on its own file.
+    pub (super) fn write_to(&mut self, path: &str) -> Result<(), String> {
+        let path = if path.is_empty() {self.doc.path.clone()} else {path.to_string()};
+        self.save(&path).map_err(|e| format!("Could not write {}: {}", path, e))?;
+        self.header_msg = format!("Written {}", path);
+        Ok(())
+    }
+
+    fn goto_line(&mut self, line: usize) {
+        let line = line.clamp(1, self.doc.buffer.len_lines()) - 1;
+        self.clear_selection();
+        self.set_cursor_position(Position::new(line, self.first_non_blank(line)));
+    }
+
+    fn set_option(&mut self, option: &str) -> Result<(), String> {
+        if let Some((name, value)) = option.split_once('=') {
+            return match name {
+                // Empty to keep backups next to the files.
+                "backupdir" | "bdir" => {
+                    self.backup_dir = value.to_string();
+                    Ok(())
+                }
+                "fileformat" | "ff" => {
+                    let line_ending = LineEnding::from_name(value).ok_or(format!("Invalid fileformat: {}", value))?;
+                    if line_ending != self.doc.line_ending {
+                        self.doc.line_ending = line_ending;
+                        self.doc.current = false;
+                    }
+                    Ok(())
+                }
+                "tabstop" | "ts" => {
+                    self.tab_width = value.parse::<u8>().ok()
+                        .filter(|width| *width > 0)
+                        .ok_or(format!("Invalid tabstop: {}", value))?;
+                    Ok(())
+                }
+                // Comma separated languages to highlight with tree-sitter.
+                "treesitter" => {
+                    let grammars = highlight::tree_sitter_grammars();
+                    let languages: Vec<String> = value.split(',').filter(|language| !language.is_empty()).map(String::from).collect();
+                    if let Some(language) = languages.iter().find(|language| !grammars.contains(&language.as_str())) {
+                        return Err(format!("No tree-sitter grammar for: {}", language));
+                    }
+                    self.tree_sitter_languages = languages;
+                    Ok(())
+                }
+                _ => Err(format!("Unknown option: {}", name)),
+            };
+        }
+        let (name, value) = parse_bool_option(option);
+        match name {
+            "backup" => self.backup = value,
+            "expandtab" => self.expand_tab = value,
+            "followcursor" => self.display_shifted_by_cursor = value,
+            "ignorecase" => self.search.ignore_case = value,
+            "linebreak" => self.wrap_at_words = value,
+            "osc52" => self.clipboard.use_osc52 = value,
+            "readonly" => self.doc.read_only = value,
+            "regex" => self.search.use_regex = value,
+            "showcursor" => self.show_cursor = value,
+            "themebg" => self.use_theme_background = value,
+            "wrap" => {
+                self.soft_wrap = value;
+                self.view.display_top_row = 0;
+                self.view.display_left_column = 0;
+            }
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn describe_options(&self) -> String {
+        let flag = |name: &str, value: bool| if value {name.to_string()} else {format!("no{}", name)};
+        format!("tabstop={} fileformat={} {} backupdir={} {} {} {} {} {} {} {} {} {} {} treesitter={}",
+            self.tab_width,
+            self.doc.line_ending.name(),
+            flag("backup", self.backup),
+            self.backup_dir,
+            flag("expandtab", self.expand_tab),
+            flag("followcursor", self.display_shifted_by_cursor),
+            flag("ignorecase", self.search.ignore_case),
+            flag("linebreak", self.wrap_at_words),
+            flag("osc52", self.clipboard.use_osc52),
+            flag("readonly", self.doc.read_only),
+            flag("regex", self.search.use_regex),
+            flag("showcursor", self.show_cursor),
+            flag("themebg", self.use_theme_background),
+            flag("wrap", self.soft_wrap),
+            self.tree_sitter_languages.join(","),
+        )
+    }
+
+}
@@ -1,9 +1,14 @@
 
+use std::borrow::Cow;
+
 use unicode_segmentation::UnicodeSegmentation;
 
 
 
 use super::super::Editor;
+use super::history::EditKind;
+use super::position::Position;
+use super::screen::display_width;
 
 impl Editor {
 
@@ -14,171 +19,196 @@ impl Editor {
         }
     }
 
-    pub (super) fn char_index(&self, line_index: usize, grapheme_index: usize) -> Option<usize> {
-        let cursor_char = self.line_at_index(line_index)?
-            .graphemes(true)
-            .take(grapheme_index)
-            .fold(0, |acc,val|{acc + val.chars().count()});
-        Some(cursor_char)
+    // Buffer (line, char) position of the grapheme position `pos`.
+    pub (super) fn char_position(&self, pos: Position) -> Option<(usize, usize)> {
+        Some((pos.line, pos.char_in(&self.line_at_index(pos.line)?)))
     }
 
-    pub (super) fn cursor_char_index(&self) -> usize {
-        self.char_index(self.cursor_line_index, self.cursor_index).unwrap()
+    pub (super) fn position_at_char(&self, at: (usize, usize)) -> Option<Position> {
+        Some(Position::at_char(at.0, &self.line_at_index(at.0)?, at.1))
     }
 
-    pub (super) fn display_rightmost_char_index(&self, line_index: usize) -> usize {
-        self.char_index(line_index, self.display_rightmost_index).unwrap()
+    // Graphemes of `line` and the columns each takes up, a tab reaches to the
+    // next tab stop.
+    pub (super) fn grapheme_widths<'a>(&self, line: &'a str) -> impl Iterator<Item = (&'a str, usize)> + 'a {
+        let tab_width = self.tab_width as usize;
+        let mut column = 0;
+        line.graphemes(true).map(move |grapheme| {
+            let width = if grapheme == "\t" {tab_width - column % tab_width} else {display_width(grapheme)};
+            column += width;
+            (grapheme, width)
+        })
     }
 
-    pub (super) fn collapse_preference(&mut self) {
-        self.cursor_prefered_index = self.cursor_index;
+    // Columns taken up by the graphemes of the line before `pos`, positions
+    // past the end take one each.
+    pub (super) fn column_of(&self, pos: Position) -> usize {
+        let line = self.line_at_index(pos.line).unwrap_or_default();
+        let mut widths = self.grapheme_widths(&line).map(|(_, width)| width);
+        (0..pos.grapheme).map(|_| widths.next().unwrap_or(1)).sum()
     }
 
-    pub (super) fn resolve_cursor_index(&mut self) {
-        self.cursor_index = self.cursor_prefered_index;
-        let len = self.cursor_current_line().graphemes(true).count();
-        if self.cursor_prefered_index > len {
-            self.cursor_index = len;
-        }
+    // Grapheme covering `column`, the end of the line when it is shorter.
+    pub (super) fn position_at_column(&self, line_index: usize, column: usize) -> Position {
+        let line = self.line_at_index(line_index).unwrap_or_default();
+        let mut end = 0;
+        let grapheme = self.grapheme_widths(&line)
+            .take_while(|(_, width)| {end += width; end <= column})
+            .count();
+        Position::new(line_index, grapheme)
+    }
+
+    pub (super) fn cursor_position(&self) -> Position {
+        self.view.cursor
+    }
+
+    pub (super) fn cursor_column(&self) -> usize {
+        self.column_of(self.view.cursor)
     }
 
-    pub (super) fn line_at_index_mut(&mut self, index: usize) -> Option<&mut String> {
-        self.lines.iter_mut().skip(index).next()
+    // Width of the grapheme under the cursor, the end of the line takes one.
+    fn cursor_width(&self) -> usize {
+        self.line_at_index(self.view.cursor.line).map_or(1, |line| {
+            self.grapheme_widths(&line).nth(self.view.cursor.grapheme).map_or(1, |(_, width)| width)
+        })
     }
-    pub (super) fn line_at_index(&self, index: usize) -> Option<&String> {
-        self.lines.iter().skip(index).next()
+
+    pub (super) fn cursor_char_position(&self) -> (usize, usize) {
+        self.char_position(self.view.cursor).unwrap()
     }
-    pub (super) fn cursor_current_line_mut(&mut self) -> &mut String {
-        self.line_at_index_mut(self.cursor_line_index).unwrap()
+
+    pub (super) fn collapse_preference(&mut self) {
+        self.view.cursor_prefered_column = self.cursor_column();
+        self.view.cursor_prefered_row_column = None;
     }
-    pub (super) fn cursor_current_line(&self) -> &String {
-        self.line_at_index(self.cursor_line_index).unwrap()
+
+    pub (super) fn resolve_cursor_grapheme(&mut self) {
+        self.view.cursor = self.position_at_column(self.view.cursor.line, self.view.cursor_prefered_column);
+    }
+
+    pub (super) fn line_at_index(&self, index: usize) -> Option<Cow<'_, str>> {
+        self.doc.buffer.line(index)
+    }
+
+    pub (super) fn set_cursor_position(&mut self, pos: Position) {
+        self.view.cursor = pos;
+        self.collapse_preference();
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
     }
 
     pub (super) fn move_cursor_right(&mut self) {
-        if self.cursor_index < self.lines.iter().skip(self.cursor_line_index).next().unwrap().len() {
-            self.cursor_index += 1;
+        if self.view.cursor.grapheme < self.line_grapheme_count(self.view.cursor.line) {
+            self.view.cursor.grapheme += 1;
             if self.display_shifted_by_cursor {self.clamp_display_to_cursor(); }
             self.collapse_preference();
-        } else if self.cursor_line_index < self.lines.iter().count() - 1 {
+        } else if self.view.cursor.line < self.doc.buffer.len_lines() - 1 {
             self.move_cursor_down();
             self.move_cursor_to_start_of_line();
         }
     }
     pub (super) fn move_cursor_left(&mut self) -> bool {
-        if self.cursor_index > 0 {
-            self.cursor_index -= 1;
+        if self.view.cursor.grapheme > 0 {
+            self.view.cursor.grapheme -= 1;
             if self.display_shifted_by_cursor {self.clamp_display_to_cursor(); }
             self.collapse_preference();
-            return true;
-        } else if self.cursor_line_index > 0 {
+            true
+        } else if self.view.cursor.line > 0 {
             self.move_cursor_up();
             self.move_cursor_to_end_of_line();
-            return true;
+            true
         } else {
             self.collapse_preference();
-            return false;
+            false
         }
     }
     pub (super) fn move_cursor_up(&mut self) {
-        if self.cursor_line_index > 0 {
-            self.cursor_line_index -= 1;
-            self.resolve_cursor_index();
+        if self.soft_wrap {
+            self.move_cursor_row(false);
+        } else if self.view.cursor.line > 0 {
+            self.view.cursor.line -= 1;
+            self.resolve_cursor_grapheme();
 
             if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
         }
     }
     pub (super) fn move_cursor_down(&mut self) {
-        let len = self.lines.len();
+        let len = self.doc.buffer.len_lines();
         let max = if len == 0 {0} else {len-1};
-        if self.cursor_line_index + 1 <= max {
-            self.cursor_line_index += 1;
-            self.resolve_cursor_index();
+        if self.soft_wrap {
+            self.move_cursor_row(true);
+        } else if self.view.cursor.line < max {
+            self.view.cursor.line += 1;
+            self.resolve_cursor_grapheme();
 
             if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
         }
     }
     pub (super) fn move_cursor_to_end_of_line(&mut self) {
-        let len = self.cursor_current_line().graphemes(true).count();
-        self.cursor_index = len;
+        self.view.cursor.grapheme = self.line_grapheme_count(self.view.cursor.line);
         self.collapse_preference();
     }
     pub (super) fn move_cursor_to_start_of_line(&mut self) {
-        self.cursor_index = 0;
+        self.view.cursor.grapheme = 0;
         self.collapse_preference();
     }
-    pub (super) fn linesplit_at_cursor(&mut self) {
-        let second_half: String;
-        {
-            let cursor_index = self.cursor_index;
-            let current_line = self.cursor_current_line_mut();
-            let first_half = String::from(&current_line[0..cursor_index]);
-            second_half = String::from(&current_line[cursor_index..]);
-            *current_line = first_half;
-        }
-        self.lines.insert(self.cursor_line_index+1, second_half);
-        self.cursor_index = 0;
-        self.cursor_line_index += 1;
-        self.collapse_preference();
-    }
-
+    // Types `c` and moves past it. A combining mark joins the grapheme before
+    // it, so the cursor goes to whichever grapheme follows the char.
     pub (super) fn put_char_on_cursor(&mut self, c: char) {
-        let index = self.cursor_char_index();
-        self.cursor_current_line_mut().insert(index, c);
-        self.current = false;
+        let (line_index, char_index) = self.cursor_char_position();
+        self.insert_text((line_index, char_index), c.encode_utf8(&mut [0; 4]), EditKind::Insert);
+        self.view.cursor = self.position_at_char((line_index, char_index + 1)).unwrap();
         self.collapse_preference();
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
     }
     pub (super) fn remove_grapheme_on_cursor(&mut self) {
-        let cursor_index = self.cursor_index;
-        let len_current_line: usize;
-        {
-            let current_line = self.cursor_current_line_mut();
-            len_current_line = current_line.graphemes(true).count();
-            if cursor_index < len_current_line {
-                *current_line = current_line
-                    .graphemes(true)
-                    .enumerate()
-                    .filter(|(i,_)| *i != cursor_index)
-                    .map(|(_,g)|g)
-                    .collect::<String>();
-                self.current = false;
-                self.collapse_preference();
-                return;
-            }
-        } 
-        if cursor_index == len_current_line {
-            let next_line_content: String;
-            if let Some(next_line) = self.line_at_index(self.cursor_line_index+1) {
-                next_line_content = next_line.clone();
-            } else {
-                self.collapse_preference();
-                return;
-            }
-            self.lines.remove(self.cursor_line_index+1);
-            self.cursor_current_line_mut().push_str(next_line_content.as_str());
-            self.collapse_preference();
-            self.current = false;
+        let cursor = self.view.cursor;
+        let from = self.cursor_char_position();
+        if cursor.grapheme < self.line_grapheme_count(cursor.line) {
+            let to = self.char_position(Position::new(cursor.line, cursor.grapheme + 1)).unwrap();
+            self.remove_text(from, to, EditKind::Delete);
+        } else if cursor.line + 1 < self.doc.buffer.len_lines() {
+            self.remove_text(from, (cursor.line + 1, 0), EditKind::Delete);
+        }
+        self.collapse_preference();
+    }
+    // Backspace, takes the selection or else the grapheme before the cursor.
+    pub (super) fn backspace(&mut self) {
+        if !self.delete_selection() && self.move_cursor_left() {
+            self.remove_grapheme_on_cursor();
         }
     }
     pub (super) fn move_display_down(&mut self) {
-        self.display_top_line_index += 1;
+        if self.soft_wrap {
+            self.scroll_wrapped_down();
+            return;
+        }
+        self.view.display_top_line_index += 1;
     }
     pub (super) fn move_display_up(&mut self) {
-        self.display_top_line_index = if self.display_top_line_index == 0 {0} else {self.display_top_line_index-1};
+        if self.soft_wrap {
+            self.scroll_wrapped_up();
+            return;
+        }
+        self.view.display_top_line_index = if self.view.display_top_line_index == 0 {0} else {self.view.display_top_line_index-1};
     }
     pub (super) fn clamp_display_to_cursor(&mut self) {
-        let max_displayed_line_index = self.display_top_line_index + (self.text_size.1 - 1);
-        let min_displayed_line_index = self.display_top_line_index;
-        if self.cursor_line_index < min_displayed_line_index {
-            self.display_top_line_index = self.cursor_line_index;
-        } else if self.cursor_line_index > max_displayed_line_index {
-            self.display_top_line_index = self.cursor_line_index - (self.text_size.1 - 1);
+        if self.soft_wrap {
+            self.clamp_wrapped_display_to_cursor();
+            return;
+        }
+        let max_displayed_line_index = self.view.display_top_line_index + (self.text_size.1 - 1);
+        let min_displayed_line_index = self.view.display_top_line_index;
+        if self.view.cursor.line < min_displayed_line_index {
+            self.view.display_top_line_index = self.view.cursor.line;
+        } else if self.view.cursor.line > max_displayed_line_index {
+            self.view.display_top_line_index = self.view.cursor.line - (self.text_size.1 - 1);
         }
 
-        if self.cursor_index < self.display_rightmost_index {
-            self.display_rightmost_index = self.cursor_index;    
-        } else if self.cursor_index > self.display_rightmost_index + (self.text_size.0 - 1) {
-            self.display_rightmost_index = self.cursor_index - (self.text_size.0 - 1);
+        let (column, width) = (self.cursor_column(), self.cursor_width());
+        if column < self.view.display_left_column {
+            self.view.display_left_column = column;
+        } else if column + width > self.view.display_left_column + self.text_size.0 {
+            self.view.display_left_column = (column + width).saturating_sub(self.text_size.0);
         }
     }
 
@@ -0,0 +1,322 @@
+use std::cell::OnceCell;
+
+use encoding_rs::Encoding;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+use super::Editor;
+use super::EditorMode;
+use super::buffer::TextBuffer;
+use super::position::Position;
+use super::encoding::FileEncoding;
+use super::save;
+use super::history::History;
+use super::highlight::{self, Highlighter};
+
+// Cursor and scroll state of a document on screen.
+#[derive(Debug, Clone, Default)]
+pub struct View {
+    pub display_top_line_index: usize,
+    // Row of the top line shown first when it is soft wrapped.
+    pub display_top_row: usize,
+    // Column the view is scrolled to with soft wrap off.
+    pub display_left_column: usize,
+
+    pub cursor: Position,
+    // Column kept while moving up and down through shorter lines.
+    pub cursor_prefered_column: usize,
+    // Column within the row kept while moving through soft wrapped rows.
+    pub cursor_prefered_row_column: Option<usize>,
+    pub selection_anchor: Option<Position>,
+}
+
+// How the lines of a file end, new files get the platform's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Unix,
+    Dos,
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        if cfg!(windows) {LineEnding::Dos} else {LineEnding::Unix}
+    }
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Dos => "\r\n",
+        }
+    }
+
+    // As `:set fileformat` names it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(LineEnding::Unix),
+            "dos" => Some(LineEnding::Dos),
+            _ => None,
+        }
+    }
+}
+
+// What opening a file found, reported once the document is shown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OpenStatus {
+    #[default]
+    Loaded,
+    // Nothing is at the path yet, saving creates the file.
+    New,
+    // Reading the file failed with this error.
+    Unreadable(String),
+    // The file has bytes text files do not.
+    Binary,
+    // Not opened from a path, the first `:w file` names it.
+    Untitled,
+}
+
+// An open file and its undo history.
+#[derive(Debug, Default)]
+pub struct Document {
+    pub path: String,
+    pub current: bool,
+    pub buffer: TextBuffer,
+    pub history: History,
+    // Where the document was left while another one is shown.
+    pub view: View,
+    pub highlighter: Box<dyn Highlighter>,
+    // Name of the syntax found for the path, which can take reading the
+    // file's first line, so it is only looked up once.
+    pub syntax_name: OnceCell<String>,
+    // Written between lines on save, the one most lines used when loaded.
+    pub line_ending: LineEnding,
+    // The file loaded had lines ending either way, saving evens them out.
+    pub mixed_line_endings: bool,
+    // The last line ends in a line ending too.
+    pub final_newline: bool,
+    pub encoding: FileEncoding,
+    // Some bytes of the file did not decode, writing the text back would
+    // replace them.
+    pub undecodable: bool,
+    pub status: OpenStatus,
+    // Saving is refused, the file cannot be written or is not text.
+    pub read_only: bool,
+}
+
+impl Document {
+    // Empty and in sync with the file it does not have yet, undoing back to
+    // the start leaves nothing to save.
+    pub fn untitled() -> Self {
+        let mut history = History::default();
+        history.mark_saved(b"");
+        Self {
+            path: "Untitled".to_string(),
+            status: OpenStatus::Untitled,
+            current: true,
+            final_newline: true,
+            history,
+            ..Self::default()
+        }
+    }
+
+    // Reads the file as `encoding`, or as the encoding it appears to be in.
+    pub fn open(path: String, encoding: Option<&'static Encoding>) -> Self {
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Self { path, status: OpenStatus::New, ..Self::untitled() };
+            }
+            Err(e) => {
+                return Self { path, status: OpenStatus::Unreadable(e.to_string()), read_only: true, ..Self::untitled() };
+            }
+        };
+        let history = History::load(&path, &bytes).unwrap_or_else(|| {
+            let mut history = History::default();
+            history.mark_saved(&bytes);
+            history
+        });
+        let (encoding, s, decoded) = FileEncoding::decode(&bytes, encoding);
+        let crlf_count = s.matches("\r\n").count();
+        let lf_count = s.matches('\n').count() - crlf_count;
+        let line_ending = if crlf_count > lf_count {
+            LineEnding::Dos
+        } else if lf_count > 0 {
+            LineEnding::Unix
+        } else {
+            LineEnding::default()
+        };
+        let binary = encoding.is_binary(&bytes);
+        let writable = save::can_write(std::path::Path::new(&path));
+        let text = s.strip_suffix('\n').unwrap_or(&s);
+        let lines = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line));
+        Self {
+            current: history.is_saved(),
+            buffer: TextBuffer::from_lines(lines),
+            history,
+            path,
+            view: View::default(),
+            highlighter: Box::default(),
+            syntax_name: OnceCell::new(),
+            line_ending,
+            mixed_line_endings: crlf_count > 0 && lf_count > 0,
+            final_newline: s.ends_with('\n'),
+            encoding,
+            undecodable: !decoded,
+            status: if binary {OpenStatus::Binary} else {OpenStatus::Loaded},
+            read_only: binary || !writable,
+        }
+    }
+
+    pub fn syntax<'a>(&self, syntax_set: &'a SyntaxSet) -> &'a SyntaxReference {
+        let name = self.syntax_name.get_or_init(|| highlight::find_syntax(syntax_set, &self.path).name.clone());
+        syntax_set.find_syntax_by_name(name).unwrap_or_else(|| highlight::find_syntax(syntax_set, &self.path))
+    }
+
+    pub fn title(&self) -> String {
+        let mut ret = if self.current {String::new()} else {"*".to_string()};
+        ret.push_str(&self.path);
+        if self.read_only {
+            ret.push_str(" [RO]");
+        }
+        ret
+    }
+
+    // Encoding and line endings, as `:set fileformat` changes them.
+    pub fn file_format(&self) -> String {
+        format!("[{} {}]", self.encoding.name(), self.line_ending.name())
+    }
+}
+
+impl Editor {
+
+    pub (super) fn document_count(&self) -> usize {
+        self.documents.len()
+    }
+
+    // The shown document lives in `self.doc`, its slot in the list only
+    // holds a placeholder.
+    pub (super) fn document(&self, index: usize) -> &Document {
+        if index == self.active_document {&self.doc} else {&self.documents[index]}
+    }
+
+    pub (super) fn switch_document(&mut self, index: usize) {
+        if index == self.active_document || index >= self.documents.len() {
+            return;
+        }
+        if self.mode == EditorMode::Visual {
+            self.mode = EditorMode::Normal;
+        }
+        self.clear_selection();
+        self.doc.history.seal();
+        std::mem::swap(&mut self.doc.view, &mut self.view);
+        self.swap_in_document(index);
+        std::mem::swap(&mut self.doc.view, &mut self.view);
+        self.windows[self.active_window].document = index;
+        self.clamp_cursor_to_line();
+    }
+
+    pub (super) fn swap_in_document(&mut self, index: usize) {
+        std::mem::swap(&mut self.doc, &mut self.documents[self.active_document]);
+        std::mem::swap(&mut self.doc, &mut self.documents[index]);
+        self.active_document = index;
+    }
+
+    // Ctrl+Tab and Ctrl+Shift+Tab, `:bn` and `:bp`.
+    pub (super) fn cycle_document(&mut self, forward: bool) {
+        let count = self.document_count();
+        let offset = if forward {1} else {count - 1};
+        self.switch_document((self.active_document + offset) % count);
+    }
+
+    // Shows the document for `path`, opening it first if it is not open yet.
+    pub (super) fn open(&mut self, path: String) {
+        if let Some(index) = (0..self.document_count()).find(|i| self.document(*i).path == path) {
+            self.switch_document(index);
+            return;
+        }
+        self.documents.push(Document::open(path, self.encoding));
+        self.switch_document(self.documents.len() - 1);
+        self.report_loaded_document();
+    }
+
+    // Replaces the shown document with what is on disk.
+    pub (super) fn reload(&mut self) {
+        self.store_history();
+        self.doc = Document::open(self.doc.path.clone(), self.encoding);
+        self.view = View::default();
+        self.report_loaded_document();
+    }
+
+    // Warns about anything loading the shown document had to even out.
+    pub (super) fn report_loaded_document(&mut self) {
+        let path = &self.doc.path;
+        self.header_msg = match &self.doc.status {
+            OpenStatus::New => format!("New file {}", path),
+            OpenStatus::Unreadable(e) => format!("Could not read {}: {}, opened read-only", path, e),
+            OpenStatus::Binary => format!("{} looks like a binary file, opened read-only", path),
+            OpenStatus::Loaded if self.doc.undecodable => {
+                format!("{} is not valid {}, it cannot be saved", path, self.doc.encoding.name())
+            }
+            OpenStatus::Loaded if self.doc.read_only => format!("{} cannot be written, opened read-only", path),
+            OpenStatus::Loaded if self.doc.mixed_line_endings => {
+                format!("{} mixes line endings, saving uses {} ones", path, self.doc.line_ending.name())
+            }
+            OpenStatus::Loaded | OpenStatus::Untitled => return,
+        };
+    }
+
+    pub (super) fn close_document(&mut self) {
+        self.store_history();
+        if self.document_count() == 1 {
+            self.doc = Document::untitled();
+            self.view = View::default();
+            return;
+        }
+        let closing = self.active_document;
+        self.cycle_document(closing + 1 < self.document_count());
+        self.documents.remove(closing);
+        if self.active_document > closing {
+            self.active_document -= 1;
+        }
+        self.forget_document(closing, self.active_document);
+        self.windows[self.active_window].document = self.active_document;
+    }
+
+    // First document with changes that are not written out yet.
+    pub (super) fn unsaved_document(&self) -> Option<usize> {
+        (0..self.document_count()).find(|i| !self.document(*i).current)
+    }
+
+    pub (super) fn store_all_histories(&mut self) {
+        for index in 0..self.document_count() {
+            let document = self.document(index);
+            if let Err(e) = document.history.store(&document.path) {
+                self.header_msg = format!("Could not store undo history: {}", e);
+            }
+        }
+    }
+
+    // `:ls`, one entry per document with `%` on the shown one and `+` on the
+    // modified ones.
+    pub (super) fn describe_documents(&self) -> String {
+        (0..self.document_count())
+            .map(|i| {
+                let document = self.document(i);
+                format!("{}{}{} \"{}\"",
+                    i + 1,
+                    if i == self.active_document {"%"} else {""},
+                    if document.current {""} else {"+"},
+                    document.path)
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+}
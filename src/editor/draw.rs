@@ -1,9 +1,12 @@
-use std::{io::Write};
+use std::io::Write;
 
 use super::Editor;
+use super::EditorMode;
+use super::search;
+use super::screen::{Screen, CellStyle, display_width};
+use super::wrap::ScreenRow;
 
-use crossterm::{*, style::{Print}};
-use syntect::{easy::HighlightLines};
+use crossterm::{*, style::{Color, Print}};
 use unicode_segmentation::UnicodeSegmentation;
 
 impl Editor {
@@ -11,83 +14,164 @@ impl Editor {
     pub fn draw<W>(&mut self, out: &mut W, at: (u16,u16), size: (u16,u16))
         where W: Write
     {
-        self.draw_title(out, at, size.0);
+        let mut screen = std::mem::take(&mut self.screen);
+        screen.begin_frame((at.0 + size.0, at.1 + size.1));
+
+        self.draw_title(&mut screen, at, size.0);
 
         let title_thickness = 1;
+        let command_line_thickness = 1;
+
+        let mut panes = Vec::new();
+        let mut separators = Vec::new();
+        self.layout.arrange(
+            (at.0, at.1 + title_thickness),
+            (size.0, size.1.saturating_sub(title_thickness + command_line_thickness)),
+            &mut panes, &mut separators);
+
+        // Every window gets a status line once there is more than one.
+        let status_line_thickness = if panes.len() > 1 {1} else {0};
+        let focused = self.active_window;
+        for pane in panes.iter() {
+            self.swap_in_window(pane.window);
+            let text_height = pane.size.1.saturating_sub(status_line_thickness);
+            self.draw_window(&mut screen, pane.at, (pane.size.0, text_height), pane.window == focused);
+            if status_line_thickness > 0 {
+                self.draw_status_line(&mut screen, (pane.at.0, pane.at.1 + text_height), pane.size.0, pane.window == focused);
+            }
+        }
+        self.swap_in_window(focused);
+        self.panes = panes;
+
+        for (separator_at, height) in separators {
+            for y_offset in 0..height {
+                screen.put((separator_at.0, separator_at.1 + y_offset), "│", CellStyle::default());
+            }
+        }
+
+        self.draw_command_line(&mut screen, (at.0, at.1 + size.1.saturating_sub(command_line_thickness)), size.0);
+
+        screen.flush(out).unwrap();
+        self.screen = screen;
+
+        if let Some(osc52) = self.clipboard.take_pending_osc52() {
+            out.queue(Print(osc52)).unwrap();
+        }
+
+        out.flush().unwrap();
+    }
 
-        let mut largest_line_number = self.display_top_line_index + self.text_size.1;
+    // Line numbers and text of the focused window.
+    fn draw_window(&mut self, screen: &mut Screen, at: (u16,u16), size: (u16,u16), focused: bool) {
+        let mut largest_line_number = self.view.display_top_line_index + size.1 as usize;
         let mut digit_count: usize = 1;
         let line_numbers_thickness = loop {
             largest_line_number /= 10;
             if largest_line_number == 0 {break digit_count;}
             else {digit_count += 1;}
         } + 1;
-        
+
         let size = (size.0 as usize, size.1 as usize);
-        self.set_text_size((size.0 - line_numbers_thickness, size.1 - title_thickness));
+        self.set_text_size((size.0.saturating_sub(line_numbers_thickness).max(1), size.1.max(1)));
 
-        self.draw_line_numbers(out, (at.0, at.1 + 1), line_numbers_thickness, digit_count);
-        let text_at = (at.0 + line_numbers_thickness as u16, at.1 + title_thickness as u16);
-        self.draw_text(out, text_at);
+        self.prepare_highlighting();
+        let rows = self.screen_rows();
+        self.draw_line_numbers(screen, at, &rows, line_numbers_thickness, digit_count);
+        let text_at = (at.0 + line_numbers_thickness as u16, at.1);
+        self.draw_text(screen, text_at, &rows, focused);
+    }
 
-        out.flush().unwrap();
+    pub fn draw_status_line(&self, screen: &mut Screen, at: (u16,u16), length: u16, focused: bool) {
+        let background = if focused {Color::White} else {Color::DarkGrey};
+        let status = format!(" {} {}:{} {}", self.doc.title(), self.view.cursor.line + 1, self.cursor_column() + 1,
+            self.doc.file_format());
+        let mut width = 0;
+        let status: String = status.graphemes(true)
+            .take_while(|grapheme| {width += display_width(grapheme); width <= length as usize})
+            .collect();
+        let style = CellStyle::new(Color::Black, background);
+        screen.put(at, &" ".repeat(length as usize), style);
+        screen.put(at, &status, style);
     }
 
-    pub fn draw_title<W>(&self, out: &mut W, at: (u16,u16), length: u16) 
-        where W: Write
-    {
-        let header_msg = &self.header_msg;
-
-        out.queue(style::SetForegroundColor(style::Color::Black)).unwrap();
-        out.queue(style::SetBackgroundColor(style::Color::Blue)).unwrap();
-        out.queue(cursor::MoveTo(at.0, at.1)).unwrap();
-        let mut header = format!("~ {} ~ {}", self.title(), header_msg);
-        while header.graphemes(true).count() < length as usize {
-            header.push_str(" ");
+    // Mode, a tab per open document with the format of the active one and
+    // the latest message.
+    pub fn draw_title(&self, screen: &mut Screen, at: (u16,u16), length: u16) {
+        let mut segments = vec![(format!("~ {} ~", self.mode), false)];
+        for index in 0..self.document_count() {
+            let document = self.document(index);
+            let text = if index == self.active_document {
+                format!(" {}:{} {} ", index + 1, document.title(), document.file_format())
+            } else {
+                format!(" {}:{} ", index + 1, document.title())
+            };
+            segments.push((text, index == self.active_document));
         }
-        out.queue(Print(header)).unwrap();
-        out.queue(style::ResetColor).unwrap();
-    }
+        segments.push((format!("~ {}", self.header_msg), false));
 
-    pub fn draw_line_numbers<W>(&self, out: &mut W, at: (u16,u16), thickness: usize, digit_count: usize) 
-        where W: Write 
-    {
-        let height = self.text_size.1;
-        let num_lines = self.lines.iter().count();
-        let gap_size = thickness - digit_count;
-        for y_offset in 0..height {
-            let mut line_number = y_offset + self.display_top_line_index+1;
-            for digit_offset in (0..digit_count).rev() {
-                let digit = if line_number == 0 || y_offset + self.display_top_line_index >= num_lines {
-                    " ".to_string()
-                } else {
-                    (line_number % 10).to_string()
-                };
-                
-                out.queue(cursor::MoveTo(at.0 + digit_offset as u16, at.1 + y_offset as u16)).unwrap();
-                out.queue(Print(digit)).unwrap();
+        let mut x = at.0;
+        for (text, active) in segments {
+            let background = if active {Color::White} else {Color::Blue};
+            x += screen.put((x, at.1), &text, CellStyle::new(Color::Black, background));
+        }
+        if x < at.0 + length {
+            screen.put((x, at.1), &" ".repeat((at.0 + length - x) as usize), CellStyle::new(Color::Black, Color::Blue));
+        }
+    }
 
-                line_number /= 10;
+    pub fn draw_command_line(&self, screen: &mut Screen, at: (u16,u16), length: u16) {
+        let (mut line, cursor_at) = match self.mode {
+            EditorMode::Command => (format!(":{}", self.command_line.input), self.command_line.cursor + 1),
+            EditorMode::Search => {
+                let flags = format!("{}{}",
+                    if self.search.use_regex {"[regex]"} else {""},
+                    if self.search.ignore_case {"[nocase]"} else {""});
+                let prompt = format!("{}{}", flags, if self.search.backward {"?"} else {"/"});
+                let cursor_at = prompt.chars().count() + self.search.prompt.cursor;
+                (format!("{}{}", prompt, self.search.prompt.input), cursor_at)
+            }
+            EditorMode::Confirm => {
+                let prompt = self.replace_prompt();
+                let cursor_at = prompt.chars().count();
+                (prompt, cursor_at)
             }
-            for x_offset in 0..gap_size {
-                out.queue(cursor::MoveTo(at.0 + (digit_count + x_offset) as u16, at.1 + y_offset as u16)).unwrap();
-                out.queue(Print(" ")).unwrap();
+            _ => {
+                screen.put(at, &" ".repeat(length as usize), CellStyle::default());
+                return;
             }
+        };
+        while line.chars().count() <= cursor_at.max((length as usize).saturating_sub(1)) {
+            line.push(' ');
         }
+        let before = line.chars().take(cursor_at).collect::<String>();
+        let under = line.chars().nth(cursor_at).unwrap();
+        let after = line.chars().skip(cursor_at + 1).take((length as usize).saturating_sub(cursor_at + 1)).collect::<String>();
+        let mut x = at.0;
+        x += screen.put((x, at.1), &before, CellStyle::default());
+        x += screen.put((x, at.1), &under.to_string(), CellStyle::new(Color::Black, Color::White));
+        screen.put((x, at.1), &after, CellStyle::default());
     }
 
-    pub fn draw_text<W>(&self, out: &mut W, at: (u16,u16))
-        where W: Write
-    {
-        let syntax = 
-            self.syntax_set.find_syntax_for_file(&self.path).unwrap().unwrap_or(
-            self.syntax_set.find_syntax_by_extension("txt").unwrap_or_else(
-            || {
-                eprint!("Critical Error Loading Highlighter");
-                std::process::exit(1);
-            }
-        ));
-        
+    // Line numbers of the rows' lines, continuation rows of wrapped lines
+    // get a marker instead.
+    pub fn draw_line_numbers(&self, screen: &mut Screen, at: (u16,u16), rows: &[ScreenRow], thickness: usize, digit_count: usize) {
+        let num_lines = self.doc.buffer.len_lines();
+        for (y_offset, row) in rows.iter().enumerate() {
+            let number = if row.line_index >= num_lines {
+                String::new()
+            } else if row.row > 0 {
+                "↪".to_string()
+            } else {
+                (row.line_index + 1).to_string()
+            };
+            let number = format!("{:>digits$}{}", number, " ".repeat(thickness - digit_count), digits = digit_count);
+            screen.put((at.0, at.1 + y_offset as u16), &number, CellStyle::default());
+        }
+    }
+
+    pub fn draw_text(&self, screen: &mut Screen, at: (u16,u16), rows: &[ScreenRow], focused: bool) {
+        use syntect::highlighting::{Color, FontStyle, Style, StyleModifier};
+
         let default_theme = syntect::highlighting::Theme::default();
         let theme: &syntect::highlighting::Theme = if self.theme_set.themes.contains_key(&self.theme_name) {
                 &self.theme_set.themes[&self.theme_name]
@@ -95,55 +179,90 @@ impl Editor {
                 &default_theme
             };
 
-        let mut highlight_lines = HighlightLines::new(syntax, &theme);
-        
-        for (file_line_num, line) in self.lines.iter()
-            .chain(std::iter::repeat(&String::from("")))
-            .enumerate()
-            .take_while(|(file_line_num, _)| *file_line_num < (self.display_top_line_index + self.text_size.1))
-        {   
-            let mut line = line.clone();
-            let grapheme_count = line.graphemes(true).count();
-            
-            let disp_len = if grapheme_count > self.display_rightmost_index {
-                grapheme_count - self.display_rightmost_index
-            } else {0};
-
-            if disp_len < self.text_size.0 {
-                line.push_str(std::iter::repeat(" ").take(self.text_size.0 - disp_len).collect::<String>().as_str());
-            }
+        let first_line = rows.first().map_or(0, |row| row.line_index);
+        let last_line = rows.last().map_or(0, |row| row.line_index + 1);
+        // Lines the highlighter has not got to yet are drawn unstyled.
+        let line_styles = self.line_styles(first_line..last_line);
+        let plain = syntect::highlighting::Highlighter::new(theme).get_default();
+
+        let found = StyleModifier {
+            foreground: Some(Color::BLACK),
+            background: Some(theme.settings.find_highlight.unwrap_or(Color { r: 0xE5, g: 0xC0, b: 0x7B, a: 0xFF })),
+            font_style: None
+        };
+        let selected = StyleModifier {
+            foreground: None,
+            background: Some(theme.settings.selection.unwrap_or(Color { r: 0x44, g: 0x55, b: 0x77, a: 0xFF })),
+            font_style: None
+        };
+        let cursor = StyleModifier {
+            foreground: Some(Color::BLACK),
+            background: Some(Color::WHITE),
+            font_style: Some(FontStyle::BOLD)
+        };
+
+        for (y_offset, row) in rows.iter().enumerate() {
+            let line = self.line_at_index(row.line_index).unwrap_or_default();
 
-            let mut ranges = highlight_lines.highlight_line(&line, &self.syntax_set).unwrap();
-            
-            if grapheme_count > disp_len {
-                (_,ranges) = syntect::util::split_at(&ranges[..], self.display_rightmost_index)    
+            // Style and width of every grapheme of the line, plus a cell after
+            // the last one that stands for the newline.
+            let mut spans = line_styles[row.line_index - first_line].iter().flatten().peekable();
+            let mut byte = 0;
+            let mut cells: Vec<(&str, usize, Style)> = self.grapheme_widths(&line)
+                .map(|(grapheme, width)| {
+                    while spans.next_if(|(_, range)| range.end <= byte).is_some() {}
+                    byte += grapheme.len();
+                    (grapheme, width, spans.peek().map_or(plain, |(style, _)| *style))
+                })
+                .collect();
+            cells.push((" ", 1, plain));
+
+            let mut modify = |graphemes: std::ops::Range<usize>, modifier: StyleModifier| {
+                for cell in cells.iter_mut().take(graphemes.end).skip(graphemes.start) {
+                    cell.2 = cell.2.apply(modifier);
+                }
+            };
+            if let Some(pattern) = self.search.pattern.as_ref().filter(|_| self.search.highlight) {
+                for graphemes in search::line_matches(pattern, &line) {
+                    modify(graphemes, found);
+                }
             }
-            if disp_len > self.text_size.0 {
-                (ranges,_) = syntect::util::split_at(&ranges[..], self.text_size.0);
+            if let Some(graphemes) = self.selection_on_line(row.line_index) {
+                modify(graphemes, selected);
+            }
+            if focused && self.show_cursor && row.line_index == self.view.cursor.line {
+                modify(self.view.cursor.grapheme..self.view.cursor.grapheme + 1, cursor);
             }
-            
-            if file_line_num >= self.display_top_line_index {
-                if self.show_cursor 
-                    && file_line_num == self.cursor_line_index 
-                    && self.cursor_index >= self.display_rightmost_index
-                    && self.cursor_index < self.display_rightmost_index + self.text_size.0 + 1
-                {
-                    let cursor_char_index = self.cursor_char_index() - self.display_rightmost_char_index(file_line_num);
-                    use syntect::highlighting::*;
-                    ranges = syntect::util::modify_range(&ranges, cursor_char_index..cursor_char_index+1, 
-                        StyleModifier { 
-                            foreground: Some(Color::BLACK),
-                            background: Some(Color::WHITE),
-                            font_style: Some(FontStyle::BOLD)
-                        });
-                }
-
-                let escaped = syntect::util::as_24_bit_terminal_escaped(&ranges[..], self.use_theme_background);
 
-                let display_line_num = file_line_num - self.display_top_line_index;
-                out.queue(cursor::MoveTo(at.0, at.1 + display_line_num as u16)).unwrap();
-                out.queue(Print(escaped)).unwrap();
+            let rgb = |color: Color| style::Color::Rgb { r: color.r, g: color.g, b: color.b };
+            // Without the theme background only backgrounds that mark
+            // something (selection, matches, cursor) are shown.
+            let cell_style = |style: Style| {
+                let background = if self.use_theme_background || Some(style.background) != theme.settings.background {
+                    Some(rgb(style.background))
+                } else {
+                    None
+                };
+                CellStyle { foreground: Some(rgb(style.foreground)), background }
+            };
+            let y = at.1 + y_offset as u16;
+            let row_end = row.end.min(row.start + self.text_size.0);
+            screen.put((at.0, y), &" ".repeat(self.text_size.0), cell_style(plain));
+            let mut column = 0;
+            for (grapheme, width, style) in cells {
+                let (from, to) = (column, column + width);
+                column = to;
+                if to <= row.start || from >= row_end {
+                    continue;
+                }
+                if from < row.start || to > row_end || grapheme == "\t" {
+                    // Tabs and wide graphemes that only partly fit are blank.
+                    let visible = from.max(row.start)..to.min(row_end);
+                    screen.put((at.0 + (visible.start - row.start) as u16, y), &" ".repeat(visible.len()), cell_style(style));
+                } else {
+                    screen.put((at.0 + (from - row.start) as u16, y), grapheme, cell_style(style));
+                }
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,94 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+
+// The character encoding of a file, kept to write it back the way it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileEncoding {
+    pub encoding: &'static Encoding,
+    // The file starts with a byte order mark.
+    pub bom: bool,
+}
+
+impl Default for FileEncoding {
+    fn default() -> Self {
+        Self { encoding: UTF_8, bom: false }
+    }
+}
+
+impl FileEncoding {
+    // Decodes `bytes` as `forced`, or as what the byte order mark or the
+    // bytes themselves suggest. The flag is false when some bytes did not
+    // decode and were replaced in the text.
+    pub fn decode(bytes: &[u8], forced: Option<&'static Encoding>) -> (Self, String, bool) {
+        let bom = Encoding::for_bom(bytes);
+        let encoding = forced
+            .or(bom.map(|(encoding, _)| encoding))
+            .or_else(|| guess_utf16(bytes))
+            .unwrap_or_else(|| {
+                if std::str::from_utf8(bytes).is_ok() {
+                    return UTF_8;
+                }
+                let mut detector = chardetng::EncodingDetector::new();
+                detector.feed(bytes, true);
+                detector.guess(None, true)
+            });
+        let (bom, body) = match bom {
+            Some((bom_encoding, length)) if bom_encoding == encoding => (true, &bytes[length..]),
+            _ => (false, bytes),
+        };
+        let file_encoding = Self { encoding, bom };
+        match encoding.decode_without_bom_handling_and_without_replacement(body) {
+            Some(text) => (file_encoding, text.into_owned(), true),
+            None => (file_encoding, encoding.decode_without_bom_handling(body).0.into_owned(), false),
+        }
+    }
+
+    // None when the text has characters the encoding has no bytes for.
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if self.bom {
+            bytes.extend_from_slice(match self.encoding {
+                e if e == UTF_16LE => b"\xFF\xFE",
+                e if e == UTF_16BE => b"\xFE\xFF",
+                _ => b"\xEF\xBB\xBF",
+            });
+        }
+        // encoding_rs only decodes UTF-16, its encoder writes UTF-8 instead.
+        if self.encoding == UTF_16LE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        } else if self.encoding == UTF_16BE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        } else {
+            let (encoded, used, unmappable) = self.encoding.encode(text);
+            if unmappable || used != self.encoding {
+                return None;
+            }
+            bytes.extend_from_slice(&encoded);
+        }
+        Some(bytes)
+    }
+
+    // Text has no zero bytes outside UTF-16, looking at the start like git
+    // does is enough to tell.
+    pub fn is_binary(&self, bytes: &[u8]) -> bool {
+        self.encoding != UTF_16LE && self.encoding != UTF_16BE && bytes.iter().take(8000).any(|b| *b == 0)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+}
+
+// UTF-16 without a byte order mark, text that is mostly ASCII has a zero in
+// every other byte and none in the others.
+fn guess_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let units = bytes.len() / 2;
+    if units == 0 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let zeros = |first: usize| bytes.iter().skip(first).step_by(2).filter(|b| **b == 0).count();
+    match (zeros(0), zeros(1)) {
+        (0, odd) if odd * 2 > units => Some(UTF_16LE),
+        (even, 0) if even * 2 > units => Some(UTF_16BE),
+        _ => None,
+    }
+}
@@ -1,28 +1,77 @@
 use super::Editor;
 use super::EditorMode;
+use super::clipboard;
 
 use crossterm::event;
 
 impl Editor {
     pub fn process_event(&mut self, event: event::Event) -> Result<bool, Box<dyn std::error::Error>> {
+        if let event::Event::Key(key_event) = event {
+            if std::mem::take(&mut self.window_prefix) {
+                self.process_window_key(key_event);
+                return Ok(true);
+            }
+        }
         match self.mode {
             EditorMode::Insert => 
                 match event {
                     event::Event::Key(key_event) => {
+                        let shift = key_event.modifiers.contains(event::KeyModifiers::SHIFT);
+                        let quit_armed = std::mem::take(&mut self.quit_armed);
                         if key_event.modifiers.contains(event::KeyModifiers::CONTROL) {
                             match key_event.code {
+                                event::KeyCode::Char('q') => {
+                                    return Ok(!self.request_quit(quit_armed));
+                                }
                                 event::KeyCode::Char('c') => {
-                                    return Ok(false);
+                                    self.copy_selection();
+                                }
+                                event::KeyCode::Char('x') => {
+                                    self.cut_selection();
+                                }
+                                event::KeyCode::Char('v') => {
+                                    self.paste(clipboard::DEFAULT_REGISTER);
                                 }
                                 event::KeyCode::Char('s') => {
-                                    self.save()?;
+                                    if let Err(e) = self.write_to("") {
+                                        self.header_msg = e;
+                                    }
+                                }
+                                event::KeyCode::Char('z') | event::KeyCode::Char('Z') if shift => {
+                                    self.redo();
+                                }
+                                event::KeyCode::Char('z') => {
+                                    self.undo();
+                                }
+                                event::KeyCode::Char('y') => {
+                                    self.redo();
+                                }
+                                event::KeyCode::Char('f') => {
+                                    self.enter_search_mode(false);
+                                }
+                                event::KeyCode::Char('h') => {
+                                    self.open_replace_prompt();
+                                }
+                                event::KeyCode::Char('w') => {
+                                    self.window_prefix = true;
+                                }
+                                event::KeyCode::Char('l') => {
+                                    self.screen.invalidate();
                                 }
                                 event::KeyCode::Right => {
+                                    self.begin_motion(shift);
                                     self.move_cursor_to_end_of_line();
                                 }
                                 event::KeyCode::Left => {
+                                    self.begin_motion(shift);
                                     self.move_cursor_to_start_of_line();
                                 }
+                                event::KeyCode::Tab | event::KeyCode::PageDown => {
+                                    self.cycle_document(true);
+                                }
+                                event::KeyCode::BackTab | event::KeyCode::PageUp => {
+                                    self.cycle_document(false);
+                                }
                                 event::KeyCode::Down => {
                                     self.move_display_down();
                                 }
@@ -30,51 +79,143 @@ impl Editor {
                                     self.move_display_up();
                                 }
 
+                                _ => {}
+                            }
+                        } else if key_event.modifiers.contains(event::KeyModifiers::ALT) {
+                            match key_event.code {
+                                event::KeyCode::Char('z') => {
+                                    self.undo_chronological(false);
+                                }
+                                event::KeyCode::Char('y') => {
+                                    self.undo_chronological(true);
+                                }
+
                                 _ => {}
                             }
                         } else {
                             match key_event.code {
+                                event::KeyCode::Esc => {
+                                    self.enter_normal_mode();
+                                }
                                 event::KeyCode::Tab => {
-                                    let mod_pos = self.cursor_index as u8 % self.num_spaces_per_tab;
-                                    for _ in mod_pos..self.num_spaces_per_tab {
-                                        self.put_char_on_cursor(' ');
-                                        self.move_cursor_right();
-                                    }
+                                    self.type_over_selection();
+                                    self.put_tab_on_cursor();
+                                }
+                                event::KeyCode::BackTab => {
+                                    self.dedent();
                                 }
                                 event::KeyCode::Right => {
+                                    self.begin_motion(shift);
                                     self.move_cursor_right();
                                 }
                                 event::KeyCode::Left => {
+                                    self.begin_motion(shift);
                                     self.move_cursor_left();
                                 }
                                 event::KeyCode::Up => {
+                                    self.begin_motion(shift);
                                     self.move_cursor_up();
                                 }
                                 event::KeyCode::Down => {
+                                    self.begin_motion(shift);
                                     self.move_cursor_down();
                                 }
+                                event::KeyCode::Home => {
+                                    self.begin_motion(shift);
+                                    self.move_cursor_to_start_of_line();
+                                }
+                                event::KeyCode::End => {
+                                    self.begin_motion(shift);
+                                    self.move_cursor_to_end_of_line();
+                                }
+                                event::KeyCode::F(3) => {
+                                    self.clear_selection();
+                                    self.search_next(shift);
+                                }
                                 event::KeyCode::Enter => {
+                                    self.doc.history.begin_group();
+                                    self.delete_selection();
+                                    self.reindent_closer(true);
                                     self.linesplit_at_cursor();
+                                    self.doc.history.end_group();
                                 }
                                 event::KeyCode::Backspace => {
-                                    if self.move_cursor_left() {
-                                        self.remove_grapheme_on_cursor();
-                                    }
+                                    self.backspace();
                                 }
                                 event::KeyCode::Char(c) => {
+                                    self.type_over_selection();
                                     self.put_char_on_cursor(c);
-                                    self.move_cursor_right();
+                                    self.reindent_closer(false);
                                 }
 
                                 _ => {}
                             }
                         }
                     }
-                
-                _ => {}
+                    event::Event::Paste(text) => {
+                        self.put_pasted_text(&text);
+                    }
+
+                    _ => {}
+                }
+            EditorMode::Normal | EditorMode::Visual =>
+                match event {
+                    event::Event::Key(key_event) => {
+                        return self.process_normal_key(key_event);
+                    }
+                    event::Event::Paste(text) => {
+                        self.enter_insert_mode();
+                        self.put_pasted_text(&text);
+                        self.enter_normal_mode();
+                    }
+
+                    _ => {}
+                }
+            EditorMode::Command =>
+                match event {
+                    event::Event::Key(key_event) => {
+                        return self.process_command_key(key_event);
+                    }
+                    event::Event::Paste(text) => {
+                        self.command_line.insert_str(&text.replace(['\r', '\n'], " "));
+                    }
+
+                    _ => {}
+                }
+            EditorMode::Confirm => {
+                if let event::Event::Key(key_event) = event {
+                    self.process_confirm_key(key_event);
+                }
             }
+            EditorMode::Search =>
+                match event {
+                    event::Event::Key(key_event) => {
+                        self.process_search_key(key_event);
+                    }
+                    event::Event::Paste(text) => {
+                        self.search.prompt.insert_str(&text.replace(['\r', '\n'], " "));
+                        self.update_incremental_search();
+                    }
+
+                    _ => {}
+                }
         }
         Ok(true)
     }
 
+    // Whether the editor should close, asks for confirmation first when there
+    // are unsaved changes.
+    pub (super) fn request_quit(&mut self, quit_armed: bool) -> bool {
+        let unsaved = match self.unsaved_document() {
+            Some(index) if !quit_armed => index,
+            _ => {
+                self.store_all_histories();
+                return true;
+            }
+        };
+        self.quit_armed = true;
+        self.header_msg = format!("Unsaved changes in {}, press Ctrl+Q again to quit", self.document(unsaved).path);
+        false
+    }
+
 }
\ No newline at end of file
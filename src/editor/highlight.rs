@@ -0,0 +1,466 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{HighlightIterator, HighlightState, Style, Theme};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+use super::Editor;
+use super::buffer::TextBuffer;
+#[cfg(feature = "treesitter")]
+use super::treesitter;
+
+// A replacement in the text in the terms incremental parsers want, byte
+// offsets and (line, byte within the line) points.
+#[derive(Debug, Clone, Copy)]
+pub struct TextEdit {
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start: (usize, usize),
+    pub old_end: (usize, usize),
+    pub new_end: (usize, usize),
+}
+
+impl TextEdit {
+    // `removed` at `at` was just replaced by `inserted` in `buffer`.
+    pub fn new(buffer: &TextBuffer, at: (usize, usize), removed: &str, inserted: &str) -> Self {
+        let start_byte = buffer.byte_offset(at);
+        let start = (at.0, start_byte - buffer.byte_offset((at.0, 0)));
+        let end_of = |text: &str| match text.rfind('\n') {
+            Some(last_newline) => (start.0 + text.matches('\n').count(), text.len() - last_newline - 1),
+            None => (start.0, start.1 + text.len()),
+        };
+        Self {
+            start_byte,
+            old_end_byte: start_byte + removed.len(),
+            new_end_byte: start_byte + inserted.len(),
+            start,
+            old_end: end_of(removed),
+            new_end: end_of(inserted),
+        }
+    }
+}
+
+// What a highlighter gets to work with.
+pub struct HighlightContext<'a> {
+    pub buffer: &'a TextBuffer,
+    pub syntax_set: &'a SyntaxSet,
+    pub syntax: &'a SyntaxReference,
+    pub theme_name: &'a str,
+    pub theme: &'a Theme,
+}
+
+// Styles of a line as byte ranges covering all of it, None while the line
+// is not highlighted yet.
+pub type LineStyles = Option<Vec<(Style, Range<usize>)>>;
+
+// Source of the styles text is drawn with.
+pub trait Highlighter: std::fmt::Debug + Send {
+    // Tree-sitter grammar in use, None for syntect.
+    fn language(&self) -> Option<&'static str>;
+    // Called once `edit` is made to the text.
+    fn edit(&mut self, edit: &TextEdit);
+    // Gets ready to draw, e.g. by handing work to the worker thread.
+    fn prepare(&mut self, context: &HighlightContext, worker: &mut HighlightWorker);
+    // Styles of `lines`, one entry per line.
+    fn highlight_lines(&self, context: &HighlightContext, lines: Range<usize>) -> Vec<LineStyles>;
+}
+
+impl Default for Box<dyn Highlighter> {
+    fn default() -> Self {
+        Box::new(SyntectHighlighter::default())
+    }
+}
+
+// How long the worker parses before handing its states over and asking for
+// a repaint.
+const STEP_TIME: Duration = Duration::from_millis(30);
+
+// Lines between the states kept, drawing parses at most this many lines
+// before the first one shown.
+const CHECKPOINT_LINES: usize = 64;
+
+type States = (ParseState, HighlightState);
+
+// Parser and highlighter states at the start of a line.
+#[derive(Debug)]
+struct Checkpoint {
+    line_index: usize,
+    states: States,
+    // For a checkpoint from before an edit, the line up to which the states
+    // after it are still right if its own turn out unchanged.
+    known_until: usize,
+}
+
+// Parser and highlighter states at the start of every `CHECKPOINT_LINES`th
+// line highlighted so far, the worker thread extends them while drawing
+// reads them.
+#[derive(Debug, Default)]
+pub struct HighlightCache {
+    // Syntax and theme names the states were computed with.
+    key: Option<(String, String)>,
+    // In line order, those before `known_lines` hold for the text as it is.
+    checkpoints: Vec<Checkpoint>,
+    // Lines whose starting states follow from the checkpoints.
+    known_lines: usize,
+    // Bumped whenever the text or the states change, so the worker drops
+    // what it parsed from an older snapshot.
+    generation: u64,
+    // Generation the worker was last sent a job for.
+    requested: Option<u64>,
+}
+
+impl HighlightCache {
+    // The states before an edit still hold, those after it are kept in case
+    // parsing gets back to the same ones and the rest can stay.
+    pub fn edit(&mut self, edit: &TextEdit) {
+        let (start, old_end, new_end) = (edit.start.0, edit.old_end.0, edit.new_end.0);
+        let known_lines = self.known_lines;
+        self.checkpoints.retain_mut(|checkpoint| {
+            if checkpoint.line_index <= start {
+                if checkpoint.line_index >= known_lines {
+                    checkpoint.known_until = checkpoint.known_until.min(start + 1);
+                }
+            } else if checkpoint.line_index > old_end {
+                let until = if checkpoint.line_index < known_lines {known_lines} else {checkpoint.known_until};
+                checkpoint.line_index = checkpoint.line_index + new_end - old_end;
+                checkpoint.known_until = until + new_end - old_end;
+            } else {
+                return false;
+            }
+            checkpoint.known_until > checkpoint.line_index
+        });
+        self.known_lines = self.known_lines.min(start + 1);
+        self.generation += 1;
+    }
+
+    // Starts over from the first line with another syntax or theme.
+    fn reset(&mut self, key: (String, String), syntax: &SyntaxReference, theme: &Theme) {
+        let highlighter = syntect::highlighting::Highlighter::new(theme);
+        let states = (ParseState::new(syntax), HighlightState::new(&highlighter, ScopeStack::new()));
+        self.key = Some(key);
+        self.checkpoints = vec![Checkpoint { line_index: 0, states, known_until: 1 }];
+        self.known_lines = 1;
+        self.generation += 1;
+    }
+
+    // Latest checkpoint at or before `line_index` that holds, None until the
+    // worker got to the line.
+    fn checkpoint_before(&self, line_index: usize) -> Option<&Checkpoint> {
+        if line_index >= self.known_lines {
+            return None;
+        }
+        let after = self.checkpoints.partition_point(|checkpoint| checkpoint.line_index <= line_index);
+        self.checkpoints[..after].last()
+    }
+}
+
+// Background work for one document, handed to the worker by `prepare`.
+pub trait Job: Send {
+    // Works for a while and returns whether there is more to do, None when
+    // nothing came of it because the job is done or out of date.
+    fn step(&mut self, syntax_set: &SyntaxSet) -> Option<bool>;
+    // Identifies the document, a newer job for it replaces the queued one.
+    fn document(&self) -> usize;
+}
+
+// Carrying on the states of a document from a snapshot of its text.
+struct SyntectJob {
+    cache: Arc<Mutex<HighlightCache>>,
+    generation: u64,
+    buffer: TextBuffer,
+    theme: Theme,
+    // Where parsing got to, kept between steps.
+    at: Option<(usize, States)>,
+}
+
+impl Job for SyntectJob {
+    // Parses for about `STEP_TIME`, until it is back to the states an old
+    // checkpoint has.
+    fn step(&mut self, syntax_set: &SyntaxSet) -> Option<bool> {
+        // Nobody else holds the cache once its document is closed.
+        if Arc::strong_count(&self.cache) == 1 {
+            return None;
+        }
+        let line_count = self.buffer.len_lines();
+        let (mut line_index, mut states, mut last_checkpoint, old) = {
+            let cache = self.cache.lock().unwrap();
+            if cache.generation != self.generation || cache.known_lines >= line_count {
+                return None;
+            }
+            let (line_index, states) = match self.at.take() {
+                Some(at) => at,
+                None => {
+                    let checkpoint = cache.checkpoint_before(cache.known_lines - 1).unwrap();
+                    (checkpoint.line_index, checkpoint.states.clone())
+                }
+            };
+            let last_checkpoint = cache.checkpoint_before(line_index).unwrap().line_index;
+            let from = line_index.max(cache.known_lines - 1);
+            let old = cache.checkpoints.get(cache.checkpoints.partition_point(|checkpoint| checkpoint.line_index <= from))
+                .map(|checkpoint| (checkpoint.line_index, checkpoint.states.clone()));
+            (line_index, states, last_checkpoint, old)
+        };
+        let highlighter = syntect::highlighting::Highlighter::new(&self.theme);
+        let started = Instant::now();
+        let mut checkpoints = Vec::new();
+        let mut same_as_old = false;
+        while line_index < line_count - 1 && started.elapsed() < STEP_TIME {
+            let line = self.buffer.line(line_index).unwrap();
+            if let Ok(ops) = states.0.parse_line(&line, syntax_set) {
+                HighlightIterator::new(&mut states.1, &ops, &line, &highlighter).for_each(drop);
+            }
+            line_index += 1;
+            let at_old = old.as_ref().filter(|(old_index, _)| *old_index == line_index);
+            if at_old.is_some_and(|(_, old_states)| *old_states == states) {
+                same_as_old = true;
+                break;
+            }
+            if line_index - last_checkpoint >= CHECKPOINT_LINES {
+                checkpoints.push(Checkpoint { line_index, states: states.clone(), known_until: line_index + 1 });
+                last_checkpoint = line_index;
+            }
+            // The old checkpoint after this one is compared in the next step.
+            if at_old.is_some() {
+                break;
+            }
+        }
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation != self.generation {
+            return None;
+        }
+        let known_lines = cache.known_lines;
+        if same_as_old {
+            // The rest is as it was up to where the old checkpoint held.
+            let old_at = cache.checkpoints.partition_point(|checkpoint| checkpoint.line_index < line_index);
+            cache.known_lines = known_lines.max(cache.checkpoints[old_at].known_until);
+            cache.checkpoints.splice(old_at..old_at, checkpoints);
+        } else {
+            // Old checkpoints parsing went past no longer hold.
+            cache.checkpoints.retain(|checkpoint| checkpoint.line_index < known_lines || checkpoint.line_index > line_index);
+            let insert_at = cache.checkpoints.partition_point(|checkpoint| checkpoint.line_index <= line_index);
+            cache.checkpoints.splice(insert_at..insert_at, checkpoints);
+            cache.known_lines = known_lines.max(line_index + 1);
+            self.at = Some((line_index, states));
+        }
+        Some(cache.known_lines < line_count)
+    }
+
+    fn document(&self) -> usize {
+        Arc::as_ptr(&self.cache) as usize
+    }
+}
+
+// Thread filling the highlight caches in the background, started with the
+// first job.
+pub struct HighlightWorker {
+    jobs: Option<mpsc::Sender<Box<dyn Job>>>,
+    // Called whenever new states came in.
+    notify: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Default for HighlightWorker {
+    fn default() -> Self {
+        Self { jobs: None, notify: Arc::new(|| {}) }
+    }
+}
+
+impl std::fmt::Debug for HighlightWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HighlightWorker").field("running", &self.jobs.is_some()).finish()
+    }
+}
+
+impl HighlightWorker {
+    pub fn send(&mut self, job: Box<dyn Job>, syntax_set: &SyntaxSet) {
+        let jobs = self.jobs.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            let syntax_set = syntax_set.clone();
+            let notify = self.notify.clone();
+            std::thread::spawn(move || run_worker(receiver, syntax_set, notify));
+            sender
+        });
+        if jobs.send(job).is_err() {
+            self.jobs = None;
+        }
+    }
+}
+
+fn run_worker(receiver: mpsc::Receiver<Box<dyn Job>>, syntax_set: SyntaxSet, notify: Arc<dyn Fn() + Send + Sync>) {
+    let mut queue: Vec<Box<dyn Job>> = Vec::new();
+    loop {
+        if queue.is_empty() {
+            match receiver.recv() {
+                Ok(job) => queue.push(job),
+                Err(_) => return,
+            }
+        }
+        while let Ok(job) = receiver.try_recv() {
+            queue.retain(|queued| queued.document() != job.document());
+            queue.push(job);
+        }
+        // The latest job is usually for the document on screen.
+        match queue.last_mut().unwrap().step(&syntax_set) {
+            Some(more) => {
+                notify();
+                if !more {queue.pop();}
+            }
+            None => {queue.pop();}
+        }
+    }
+}
+
+// Highlighting with syntect's grammars, the line states are filled in by
+// the worker thread.
+#[derive(Debug, Default)]
+pub struct SyntectHighlighter {
+    cache: Arc<Mutex<HighlightCache>>,
+}
+
+impl Highlighter for SyntectHighlighter {
+    fn language(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn edit(&mut self, edit: &TextEdit) {
+        self.cache.lock().unwrap().edit(edit);
+    }
+
+    // Hands the rest of the document to the worker unless it is fully
+    // highlighted or already being worked on.
+    fn prepare(&mut self, context: &HighlightContext, worker: &mut HighlightWorker) {
+        let mut cache = self.cache.lock().unwrap();
+        let key = (context.syntax.name.clone(), context.theme_name.to_string());
+        if cache.key.as_ref() != Some(&key) {
+            cache.reset(key, context.syntax, context.theme);
+        }
+        if cache.known_lines >= context.buffer.len_lines() || cache.requested == Some(cache.generation) {
+            return;
+        }
+        cache.requested = Some(cache.generation);
+        let job = SyntectJob {
+            cache: self.cache.clone(),
+            generation: cache.generation,
+            buffer: context.buffer.clone(),
+            theme: context.theme.clone(),
+            at: None,
+        };
+        drop(cache);
+        worker.send(Box::new(job), context.syntax_set);
+    }
+
+    fn highlight_lines(&self, context: &HighlightContext, lines: Range<usize>) -> Vec<LineStyles> {
+        let start = self.cache.lock().unwrap().checkpoint_before(lines.start)
+            .map(|checkpoint| (checkpoint.line_index, checkpoint.states.clone()));
+        let (from, mut highlight_lines) = match start {
+            Some((from, (parse_state, highlight_state))) => (from, HighlightLines::from_state(context.theme, highlight_state, parse_state)),
+            None => return vec![None; lines.len()],
+        };
+        // Up to the first line from the checkpoint before it.
+        for line_index in from..lines.start {
+            let line = context.buffer.line(line_index).unwrap_or_default();
+            highlight_lines.highlight_line(&line, context.syntax_set).ok();
+        }
+        lines.map(|line_index| {
+            let line = match context.buffer.line(line_index) {
+                Some(line) => line,
+                None => return Some(Vec::new()),
+            };
+            let ranges = highlight_lines.highlight_line(&line, context.syntax_set).ok()?;
+            let mut at = 0;
+            Some(ranges.into_iter().map(|(style, text)| {
+                at += text.len();
+                (style, at - text.len()..at)
+            }).collect())
+        }).collect()
+    }
+}
+
+#[cfg(feature = "treesitter")]
+fn new_highlighter(language: Option<&'static str>) -> Box<dyn Highlighter> {
+    language
+        .and_then(treesitter::TreeSitterHighlighter::new)
+        .map(|highlighter| Box::new(highlighter) as Box<dyn Highlighter>)
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "treesitter"))]
+fn new_highlighter(_language: Option<&'static str>) -> Box<dyn Highlighter> {
+    Box::default()
+}
+
+// Names of the languages with a tree-sitter grammar compiled in.
+pub fn tree_sitter_grammars() -> Vec<&'static str> {
+    #[cfg(feature = "treesitter")]
+    return treesitter::GRAMMARS.iter().map(|grammar| grammar.name).collect();
+    #[cfg(not(feature = "treesitter"))]
+    Vec::new()
+}
+
+// Syntax for the file at `path`, plain text when nothing matches or the
+// file cannot be read for its first line.
+pub fn find_syntax<'a>(syntax_set: &'a SyntaxSet, path: &str) -> &'a SyntaxReference {
+    syntax_set.find_syntax_for_file(path).ok().flatten().unwrap_or(
+        syntax_set.find_syntax_by_extension("txt").unwrap_or_else(
+        || {
+            eprint!("Critical Error Loading Highlighter");
+            std::process::exit(1);
+        }
+    ))
+}
+
+impl Editor {
+
+    // Repaints whenever the worker highlighted more lines.
+    pub fn set_redraw_notifier<F>(&mut self, notify: F)
+        where F: Fn() + Send + Sync + 'static
+    {
+        self.highlight_worker.notify = Arc::new(notify);
+    }
+
+    // Makes the shown document's highlighter the one configured for its
+    // language and lets it get ready to draw.
+    pub (super) fn prepare_highlighting(&mut self) {
+        let language = self.tree_sitter_language();
+        if self.doc.highlighter.language() != language {
+            self.doc.highlighter = new_highlighter(language);
+        }
+        let default_theme = Theme::default();
+        let context = HighlightContext {
+            buffer: &self.doc.buffer,
+            syntax_set: &self.syntax_set,
+            syntax: self.doc.syntax(&self.syntax_set),
+            theme_name: &self.theme_name,
+            theme: self.theme_set.themes.get(&self.theme_name).unwrap_or(&default_theme),
+        };
+        self.doc.highlighter.prepare(&context, &mut self.highlight_worker);
+    }
+
+    // Styles of `lines` of the shown document.
+    pub (super) fn line_styles(&self, lines: Range<usize>) -> Vec<LineStyles> {
+        let default_theme = Theme::default();
+        let context = HighlightContext {
+            buffer: &self.doc.buffer,
+            syntax_set: &self.syntax_set,
+            syntax: self.doc.syntax(&self.syntax_set),
+            theme_name: &self.theme_name,
+            theme: self.theme_set.themes.get(&self.theme_name).unwrap_or(&default_theme),
+        };
+        self.doc.highlighter.highlight_lines(&context, lines)
+    }
+
+    #[cfg(feature = "treesitter")]
+    fn tree_sitter_language(&self) -> Option<&'static str> {
+        treesitter::grammar_for(&self.doc.path)
+            .map(|grammar| grammar.name)
+            .filter(|name| self.tree_sitter_languages.iter().any(|enabled| enabled == name))
+    }
+
+    #[cfg(not(feature = "treesitter"))]
+    fn tree_sitter_language(&self) -> Option<&'static str> {
+        None
+    }
+
+}
@@ -0,0 +1,378 @@
+use std::path::{Path, PathBuf};
+
+use bincode::Options;
+use serde::{Serialize, Deserialize};
+
+use super::Editor;
+use super::buffer::TextBuffer;
+use super::highlight::TextEdit;
+use super::position::Position;
+use super::save;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+// A single replacement of `removed` by `inserted` starting at the
+// (line, char) position `at`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub at: (usize, usize),
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Change {
+    fn inserted_end(&self) -> (usize, usize) {
+        TextBuffer::end_of(self.at, &self.inserted)
+    }
+    fn removed_end(&self) -> (usize, usize) {
+        TextBuffer::end_of(self.at, &self.removed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    kind: EditKind,
+    changes: Vec<Change>,
+    cursor_before: Position,
+}
+
+impl Transaction {
+    fn absorbs(&self, kind: EditKind, change: &Change) -> bool {
+        if kind != self.kind {
+            return false;
+        }
+        let last = match self.changes.last() {
+            Some(last) => last,
+            None => return false,
+        };
+        match kind {
+            EditKind::Insert => change.removed.is_empty() && change.at == last.inserted_end(),
+            EditKind::Delete => change.inserted.is_empty()
+                && (change.removed_end() == last.at || change.at == last.at),
+            EditKind::Other => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    parent: usize,
+    children: Vec<usize>,
+    // Child that redo follows, the branch most recently undone or created.
+    active_child: Option<usize>,
+    transaction: Transaction,
+}
+
+// Branching undo history. Node 0 is the root (the buffer as it was first
+// loaded) and node ids are handed out in chronological order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    #[serde(skip)]
+    sealed: bool,
+    // Every change recorded while set goes into one transaction.
+    #[serde(skip)]
+    grouping: bool,
+    // Node the buffer was at when it was last in sync with the file.
+    saved: Option<usize>,
+    file_hash: Option<u64>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: 0,
+                children: Vec::new(),
+                active_child: None,
+                transaction: Transaction { kind: EditKind::Other, changes: Vec::new(), cursor_before: Position::default() },
+            }],
+            current: 0,
+            sealed: false,
+            grouping: false,
+            saved: None,
+            file_hash: None,
+        }
+    }
+}
+
+impl History {
+    pub fn mark_saved(&mut self, content: &[u8]) {
+        self.saved = Some(self.current);
+        self.file_hash = Some(content_hash(content));
+        self.seal();
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    // Stops further edits from being merged into the latest transaction,
+    // an open group still takes them.
+    pub fn seal(&mut self) {
+        if !self.grouping {
+            self.sealed = true;
+        }
+    }
+
+    // Makes the changes recorded until `end_group` undo as a single step.
+    pub fn begin_group(&mut self) {
+        self.seal();
+        self.grouping = true;
+    }
+
+    pub fn end_group(&mut self) {
+        self.grouping = false;
+        self.seal();
+    }
+
+    pub fn record(&mut self, kind: EditKind, change: Change, cursor_before: Position) {
+        let id = self.nodes.len();
+        let current = &mut self.nodes[self.current];
+        if !self.sealed && self.current != 0 && current.children.is_empty()
+            && (self.grouping || current.transaction.absorbs(kind, &change))
+        {
+            current.transaction.changes.push(change);
+            return;
+        }
+        current.children.push(id);
+        current.active_child = Some(id);
+        self.nodes.push(Node {
+            parent: self.current,
+            children: Vec::new(),
+            active_child: None,
+            transaction: Transaction { kind, changes: vec![change], cursor_before },
+        });
+        self.current = id;
+        self.sealed = false;
+    }
+
+    fn depth(&self, mut node: usize) -> usize {
+        let mut depth = 0;
+        while node != 0 {
+            node = self.nodes[node].parent;
+            depth += 1;
+        }
+        depth
+    }
+
+    // Nodes to revert (walking up) and then apply (walking down) to get from
+    // the current node to `target`.
+    fn path_to(&self, target: usize) -> (Vec<usize>, Vec<usize>) {
+        let (mut up, mut down) = (Vec::new(), Vec::new());
+        let (mut from, mut to) = (self.current, target);
+        let (mut from_depth, mut to_depth) = (self.depth(from), self.depth(to));
+        while from != to {
+            if from_depth >= to_depth {
+                up.push(from);
+                from = self.nodes[from].parent;
+                from_depth -= 1;
+            } else {
+                down.push(to);
+                to = self.nodes[to].parent;
+                to_depth -= 1;
+            }
+        }
+        down.reverse();
+        (up, down)
+    }
+
+    fn cache_path(file_path: &str) -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        let file_path = std::fs::canonicalize(file_path).ok()?;
+        Some(cache_dir.join("kite").join("undo").join(path_key(&file_path)))
+    }
+
+    // Undo and redo follow parents and children without checking them, so a
+    // history read back has to be a tree with parents before their children.
+    fn is_consistent(&self) -> bool {
+        let count = self.nodes.len();
+        count > 0 && self.current < count && self.nodes.iter().enumerate().all(|(id, node)| {
+            (id == 0 || node.parent < id)
+                && node.children.iter().all(|child| *child > id && *child < count && self.nodes[*child].parent == id)
+                && node.active_child.is_none_or(|child| node.children.contains(&child))
+        })
+    }
+
+    // Loads the history saved for `file_path` by a previous session, as long
+    // as it was recorded against exactly `content`.
+    pub fn load(file_path: &str, content: &[u8]) -> Option<Self> {
+        Self::load_from(&Self::cache_path(file_path)?, content)
+    }
+
+    pub fn load_from(path: &Path, content: &[u8]) -> Option<Self> {
+        if std::fs::metadata(path).ok()?.len() > CACHE_SIZE_LIMIT {
+            return None;
+        }
+        let bytes = std::fs::read(path).ok()?;
+        let mut history: History = cache_options().deserialize(&bytes).ok()?;
+        if !history.is_consistent() {
+            return None;
+        }
+        let saved = history.saved.filter(|saved| *saved < history.nodes.len())?;
+        if history.file_hash != Some(content_hash(content)) {
+            return None;
+        }
+        history.current = saved;
+        history.sealed = true;
+        Some(history)
+    }
+
+    // Writes the history out with the saved node as the current one, so it
+    // lines up with the file on disk the next time it is opened.
+    pub fn store(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match Self::cache_path(file_path) {
+            Some(path) => self.store_to(&path),
+            None => Ok(()),
+        }
+    }
+
+    // The history holds the whole text, so like a private file it is only
+    // readable by its owner.
+    pub fn store_to(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let saved = match self.saved {
+            Some(saved) => saved,
+            None => return Ok(()),
+        };
+        save::create_private_dir(path.parent().unwrap())?;
+        let bytes = cache_options().serialize(&History { current: saved, ..self.clone() })?;
+        save::write_private(path, &bytes)?;
+        Ok(())
+    }
+}
+
+// Cache files bigger than this are not read, lengths inside them cannot
+// claim more either.
+const CACHE_SIZE_LIMIT: u64 = 64 << 20;
+
+// The layout `bincode::serialize` uses, with a limit on what is read.
+fn cache_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(CACHE_SIZE_LIMIT)
+}
+
+// Names a file in a cache folder after its whole path, `%` and separators
+// are escaped so that no two paths share a name.
+pub fn path_key(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('%', "%25")
+        .replace(std::path::MAIN_SEPARATOR, "%2F")
+}
+
+// 64 bit FNV-1a, stable across builds unlike std's DefaultHasher.
+fn content_hash(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Editor {
+    pub (super) fn replace_text(&mut self, from: (usize, usize), to: (usize, usize), text: &str, kind: EditKind) {
+        let cursor_before = self.cursor_position();
+        let removed = self.doc.buffer.remove(from, to);
+        self.doc.buffer.insert(from, text);
+        self.doc.highlighter.edit(&TextEdit::new(&self.doc.buffer, from, &removed, text));
+        self.doc.history.record(kind, Change { at: from, removed, inserted: text.to_string() }, cursor_before);
+        self.doc.current = self.doc.history.is_saved();
+    }
+
+    pub (super) fn insert_text(&mut self, at: (usize, usize), text: &str, kind: EditKind) {
+        self.replace_text(at, at, text, kind);
+    }
+
+    pub (super) fn remove_text(&mut self, from: (usize, usize), to: (usize, usize), kind: EditKind) {
+        self.replace_text(from, to, "", kind);
+    }
+
+    fn revert_node(&mut self, node: usize) {
+        let transaction = &self.doc.history.nodes[node].transaction;
+        for change in transaction.changes.iter().rev() {
+            self.doc.buffer.remove(change.at, change.inserted_end());
+            self.doc.buffer.insert(change.at, &change.removed);
+            self.doc.highlighter.edit(&TextEdit::new(&self.doc.buffer, change.at, &change.inserted, &change.removed));
+        }
+        self.view.cursor = transaction.cursor_before;
+        if transaction.kind == EditKind::Delete {
+            // Backspace moves the cursor before removing, so put it back where
+            // it was before the first deletion of the group.
+            self.view.cursor = self.position_at_char(transaction.changes[0].removed_end()).unwrap();
+        }
+        let parent = self.doc.history.nodes[node].parent;
+        self.doc.history.nodes[parent].active_child = Some(node);
+        self.doc.history.current = parent;
+    }
+
+    fn apply_node(&mut self, node: usize) {
+        let transaction = &self.doc.history.nodes[node].transaction;
+        for change in transaction.changes.iter() {
+            self.doc.buffer.remove(change.at, change.removed_end());
+            self.doc.buffer.insert(change.at, &change.inserted);
+            self.doc.highlighter.edit(&TextEdit::new(&self.doc.buffer, change.at, &change.removed, &change.inserted));
+        }
+        self.view.cursor = self.position_at_char(transaction.changes.last().unwrap().inserted_end()).unwrap();
+        let parent = self.doc.history.nodes[node].parent;
+        self.doc.history.nodes[parent].active_child = Some(node);
+        self.doc.history.current = node;
+    }
+
+    fn finish_history_move(&mut self) {
+        self.clear_selection();
+        self.collapse_preference();
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
+        self.doc.history.seal();
+        self.doc.current = self.doc.history.is_saved();
+    }
+
+    pub (super) fn undo(&mut self) -> bool {
+        if self.doc.history.current == 0 {
+            return false;
+        }
+        self.revert_node(self.doc.history.current);
+        self.finish_history_move();
+        true
+    }
+
+    pub (super) fn redo(&mut self) -> bool {
+        let child = match self.doc.history.nodes[self.doc.history.current].active_child {
+            Some(child) => child,
+            None => return false,
+        };
+        self.apply_node(child);
+        self.finish_history_move();
+        true
+    }
+
+    // Moves to the state created just before (or after) the current one,
+    // crossing between branches of the undo tree as needed.
+    pub (super) fn undo_chronological(&mut self, later: bool) -> bool {
+        let target = if later {
+            self.doc.history.current + 1
+        } else if self.doc.history.current > 0 {
+            self.doc.history.current - 1
+        } else {
+            return false;
+        };
+        if target >= self.doc.history.nodes.len() {
+            return false;
+        }
+        let (up, down) = self.doc.history.path_to(target);
+        for node in up {
+            self.revert_node(node);
+        }
+        for node in down {
+            self.apply_node(node);
+        }
+        self.finish_history_move();
+        true
+    }
+}
@@ -0,0 +1,207 @@
+use super::Editor;
+use super::history::EditKind;
+use super::position::Position;
+
+// How the languages of some syntaxes, named as syntect names them, indent.
+struct IndentRules {
+    syntaxes: &'static [&'static str],
+    // A line ending in one of these indents the lines after it.
+    openers: &'static [&'static str],
+    // Typed alone on a line these take it back to the indentation of the
+    // block they close, words once they are complete.
+    closers: &'static [&'static str],
+}
+
+const INDENT_RULES: &[IndentRules] = &[
+    IndentRules {
+        syntaxes: &["C", "C++", "C#", "CSS", "D", "Go", "Groovy", "Java", "JavaScript", "JSON", "Objective-C",
+            "Objective-C++", "PHP", "Perl", "Rust", "Scala"],
+        openers: &["{", "[", "("],
+        closers: &["}", "]", ")"],
+    },
+    IndentRules {
+        syntaxes: &["Python", "YAML"],
+        openers: &[":"],
+        closers: &[],
+    },
+    IndentRules {
+        syntaxes: &["Bourne Again Shell (bash)"],
+        openers: &["then", "do", "else", "{"],
+        closers: &["fi", "done", "else", "}"],
+    },
+];
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl IndentRules {
+    // Openers that are words only count as whole words.
+    fn opens_block(&self, line: &str) -> bool {
+        let line = line.trim_end();
+        self.openers.iter().any(|opener| match line.strip_suffix(opener) {
+            Some(before) => !opener.starts_with(char::is_alphanumeric) || !before.ends_with(is_word),
+            None => false,
+        })
+    }
+
+    // Whether the text typed on a line is a closer. A word is only complete
+    // once a char that is not part of it follows, or `ended` by Enter.
+    fn closes_block(&self, typed: &str, ended: bool) -> bool {
+        self.closers.iter().any(|closer| match typed.strip_prefix(closer) {
+            Some("") => ended || !closer.starts_with(char::is_alphanumeric),
+            Some(rest) => closer.starts_with(char::is_alphanumeric) && rest.chars().count() == 1 && !rest.starts_with(is_word),
+            None => false,
+        })
+    }
+}
+
+impl Editor {
+
+    fn indent_rules(&self) -> Option<&'static IndentRules> {
+        let syntax = self.doc.syntax(&self.syntax_set);
+        INDENT_RULES.iter().find(|rules| rules.syntaxes.contains(&syntax.name.as_str()))
+    }
+
+    // Tab in insert mode, spaces up to the next tab stop with expandtab and a
+    // tab character otherwise.
+    pub (super) fn put_tab_on_cursor(&mut self) {
+        if !self.expand_tab {
+            self.put_char_on_cursor('\t');
+            return;
+        }
+        let tab_width = self.tab_width as usize;
+        for _ in self.cursor_column() % tab_width..tab_width {
+            self.put_char_on_cursor(' ');
+        }
+    }
+
+    // Graphemes and columns taken up by the blanks the line starts with.
+    fn indent_of(&self, line_index: usize) -> (usize, usize) {
+        let line = self.line_at_index(line_index).unwrap_or_default();
+        self.grapheme_widths(&line)
+            .take_while(|(grapheme, _)| *grapheme == " " || *grapheme == "\t")
+            .fold((0, 0), |(count, columns), (_, width)| (count + 1, columns + width))
+    }
+
+    // Blanks taking up `columns`, tabs or spaces as expandtab says.
+    fn indent_text(&self, columns: usize) -> String {
+        let tab_width = self.tab_width as usize;
+        if self.expand_tab {
+            " ".repeat(columns)
+        } else {
+            "\t".repeat(columns / tab_width) + &" ".repeat(columns % tab_width)
+        }
+    }
+
+    // Indents the line by `columns`, the cursor and selection anchor stay on
+    // the same text.
+    fn set_indent(&mut self, line_index: usize, columns: usize) {
+        let (count, _) = self.indent_of(line_index);
+        let indent = self.indent_text(columns);
+        // Blanks are a char and a grapheme each.
+        self.replace_text((line_index, 0), (line_index, count), &indent, EditKind::Other);
+        let shift = |pos: &mut Position| if pos.line == line_index {
+            pos.grapheme = if pos.grapheme >= count {pos.grapheme - count + indent.len()} else {pos.grapheme.min(indent.len())};
+        };
+        shift(&mut self.view.cursor);
+        if let Some(anchor) = self.view.selection_anchor.as_mut() {
+            shift(anchor);
+        }
+    }
+
+    // Shift+Tab, takes the cursor line or the selected lines back to the
+    // previous tab stop.
+    pub (super) fn dedent(&mut self) {
+        let (first, last) = match self.selection_range() {
+            Some((start, end)) if end.grapheme == 0 && end.line > start.line => (start.line, end.line - 1),
+            Some((start, end)) => (start.line, end.line),
+            None => (self.view.cursor.line, self.view.cursor.line),
+        };
+        let tab_width = self.tab_width as usize;
+        self.doc.history.begin_group();
+        for line_index in first..=last {
+            let (_, columns) = self.indent_of(line_index);
+            if columns > 0 {
+                self.set_indent(line_index, (columns - 1) / tab_width * tab_width);
+            }
+        }
+        self.doc.history.end_group();
+        self.collapse_preference();
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
+    }
+
+    // Enter, the new line keeps the indentation of the one it is split from
+    // and goes a level deeper after an opener. Splitting right before the
+    // matching closer puts that on a line of its own.
+    pub (super) fn linesplit_at_cursor(&mut self) {
+        let (line_index, char_index) = self.cursor_char_position();
+        let line = self.line_at_index(line_index).unwrap().into_owned();
+        let (before, after) = line.split_at(self.view.cursor.byte_in(&line));
+        let mut inserted = String::from("\n");
+        let mut blanks_after = 0;
+        // Splitting the indentation itself just breaks the line.
+        if !before.trim_start_matches(is_blank).is_empty() {
+            let (_, columns) = self.indent_of(line_index);
+            let rules = self.indent_rules();
+            let opens = rules.is_some_and(|rules| rules.opens_block(before));
+            let deeper = if opens {columns + self.tab_width as usize} else {columns};
+            inserted.push_str(&self.indent_text(deeper));
+            let after_blanks = after.trim_start_matches(is_blank);
+            blanks_after = after.len() - after_blanks.len();
+            if opens && rules.is_some_and(|rules| rules.closers.iter().any(|closer| after_blanks.starts_with(closer))) {
+                inserted = format!("{}\n{}", inserted, self.indent_text(columns));
+            }
+        }
+        self.replace_text((line_index, char_index), (line_index, char_index + blanks_after), &inserted, EditKind::Other);
+        // The indentation is blanks, a char and a grapheme each.
+        let indent = inserted.split('\n').nth(1).unwrap_or_default();
+        self.view.cursor = Position::new(line_index + 1, indent.len());
+        self.collapse_preference();
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
+    }
+
+    // `O`, a new line above the cursor line indented like it.
+    pub (super) fn open_line_above(&mut self) {
+        let line_index = self.view.cursor.line;
+        let indent = self.indent_text(self.indent_of(line_index).1);
+        self.insert_text((line_index, 0), &format!("{}\n", indent), EditKind::Other);
+        self.set_cursor_position(Position::new(line_index, indent.len()));
+    }
+
+    // Called after typing and before Enter splits the line, once a closer
+    // stands alone on its line the line goes back to the indentation of the
+    // block it closes.
+    pub (super) fn reindent_closer(&mut self, ended: bool) {
+        let rules = match self.indent_rules() {
+            Some(rules) => rules,
+            None => return,
+        };
+        let line_index = self.view.cursor.line;
+        let line = self.line_at_index(line_index).unwrap_or_default();
+        let typed = line[..self.view.cursor.byte_in(&line)].trim_start_matches(is_blank);
+        if !rules.closes_block(typed, ended) {
+            return;
+        }
+        let tab_width = self.tab_width as usize;
+        let block = (0..line_index).rev()
+            .filter_map(|index| Some((index, self.line_at_index(index)?)))
+            .find(|(_, line)| !line.trim().is_empty());
+        let target = match block {
+            Some((index, line)) => {
+                let (_, columns) = self.indent_of(index);
+                if rules.opens_block(&line) {columns} else {columns.saturating_sub(1) / tab_width * tab_width}
+            }
+            None => 0,
+        };
+        if target < self.indent_of(line_index).1 {
+            self.set_indent(line_index, target);
+            self.collapse_preference();
+        }
+    }
+
+}
@@ -0,0 +1,339 @@
+use crossterm::event;
+
+use super::Editor;
+use super::EditorMode;
+use super::document::View;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    // One pane above the other.
+    Horizontal,
+    // Panes side by side.
+    Vertical,
+}
+
+// How the screen is tiled, leaves are window ids.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: Direction,
+        // Share of the space taken by `first`.
+        ratio: f32,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+// A document shown on screen. The focused window's view and text size live
+// in the editor itself while it has the focus.
+#[derive(Debug, Clone, Default)]
+pub struct Window {
+    pub document: usize,
+    pub view: View,
+    pub text_size: (usize, usize),
+}
+
+// Screen area given to a window by the last draw.
+#[derive(Debug, Clone, Copy)]
+pub struct Pane {
+    pub window: usize,
+    pub at: (u16, u16),
+    pub size: (u16, u16),
+}
+
+impl Layout {
+    fn contains(&self, window: usize) -> bool {
+        match self {
+            Layout::Window(id) => *id == window,
+            Layout::Split { first, second, .. } => first.contains(window) || second.contains(window),
+        }
+    }
+
+    fn first_window(&self) -> usize {
+        match self {
+            Layout::Window(id) => *id,
+            Layout::Split { first, .. } => first.first_window(),
+        }
+    }
+
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Layout::Window(id) => vec![*id],
+            Layout::Split { first, second, .. } => {
+                let mut windows = first.windows();
+                windows.extend(second.windows());
+                windows
+            }
+        }
+    }
+
+    // Splits the space of `window` between it and `new_window`.
+    pub fn split(&mut self, window: usize, new_window: usize, direction: Direction) {
+        match self {
+            Layout::Window(id) if *id == window => {
+                *self = Layout::Split {
+                    direction,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Window(window)),
+                    second: Box::new(Layout::Window(new_window)),
+                };
+            }
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.split(window, new_window, direction);
+                second.split(window, new_window, direction);
+            }
+        }
+    }
+
+    // Gives the space of `window` to the other half of its split, returns the
+    // window that now has the space.
+    pub fn remove(&mut self, window: usize) -> Option<usize> {
+        let sibling = match self {
+            Layout::Window(_) => return None,
+            Layout::Split { first, second, .. } => {
+                if **first == Layout::Window(window) {
+                    std::mem::replace(second.as_mut(), Layout::Window(0))
+                } else if **second == Layout::Window(window) {
+                    std::mem::replace(first.as_mut(), Layout::Window(0))
+                } else {
+                    return first.remove(window).or_else(|| second.remove(window));
+                }
+            }
+        };
+        *self = sibling;
+        Some(self.first_window())
+    }
+
+    // Shifts the ids above `removed` down to fill its gap.
+    pub fn renumber(&mut self, removed: usize) {
+        match self {
+            Layout::Window(id) => {
+                if *id > removed {*id -= 1;}
+            }
+            Layout::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    // Grows `window` by `delta` of the nearest split around it going in
+    // `direction`, a negative `delta` shrinks it.
+    pub fn resize(&mut self, window: usize, direction: Direction, delta: f32) -> bool {
+        match self {
+            Layout::Window(_) => false,
+            Layout::Split { direction: split_direction, ratio, first, second } => {
+                let in_first = first.contains(window);
+                if !in_first && !second.contains(window) {
+                    return false;
+                }
+                let child = if in_first {first} else {second};
+                if child.resize(window, direction, delta) {
+                    return true;
+                }
+                if *split_direction != direction {
+                    return false;
+                }
+                *ratio = (*ratio + if in_first {delta} else {-delta}).clamp(0.1, 0.9);
+                true
+            }
+        }
+    }
+
+    pub fn equalize(&mut self) {
+        if let Layout::Split { ratio, first, second, .. } = self {
+            *ratio = 0.5;
+            first.equalize();
+            second.equalize();
+        }
+    }
+
+    // Tiles the area at `at` of `size`, side by side panes are separated by
+    // a column given as its top cell and height.
+    pub fn arrange(&self, at: (u16, u16), size: (u16, u16), panes: &mut Vec<Pane>, separators: &mut Vec<((u16, u16), u16)>) {
+        match self {
+            Layout::Window(window) => panes.push(Pane { window: *window, at, size }),
+            Layout::Split { direction: Direction::Horizontal, ratio, first, second } => {
+                let first_height = ((size.1 as f32 * ratio).round() as u16).max(1).min(size.1.saturating_sub(1));
+                first.arrange(at, (size.0, first_height), panes, separators);
+                second.arrange((at.0, at.1 + first_height), (size.0, size.1 - first_height), panes, separators);
+            }
+            Layout::Split { direction: Direction::Vertical, ratio, first, second } => {
+                let available = size.0.saturating_sub(1);
+                let first_width = ((available as f32 * ratio).round() as u16).max(1).min(available.saturating_sub(1));
+                first.arrange(at, (first_width, size.1), panes, separators);
+                separators.push(((at.0 + first_width, at.1), size.1));
+                second.arrange((at.0 + first_width + 1, at.1), (available - first_width, size.1), panes, separators);
+            }
+        }
+    }
+}
+
+impl Editor {
+
+    // Moves the focus to `window` as is, used while drawing every window in
+    // turn.
+    pub (super) fn swap_in_window(&mut self, window: usize) {
+        let focused = &mut self.windows[self.active_window];
+        std::mem::swap(&mut self.view, &mut focused.view);
+        std::mem::swap(&mut self.text_size, &mut focused.text_size);
+        self.active_window = window;
+        self.swap_in_document(self.windows[window].document);
+        let focused = &mut self.windows[window];
+        std::mem::swap(&mut self.view, &mut focused.view);
+        std::mem::swap(&mut self.text_size, &mut focused.text_size);
+        self.clamp_view_to_buffer();
+    }
+
+    // Another window on the same document may have shortened it.
+    fn clamp_view_to_buffer(&mut self) {
+        let last_line = self.doc.buffer.len_lines() - 1;
+        self.view.cursor.line = self.view.cursor.line.min(last_line);
+        self.view.cursor.grapheme = self.view.cursor.grapheme.min(self.line_grapheme_count(self.view.cursor.line));
+        self.view.display_top_line_index = self.view.display_top_line_index.min(last_line);
+        if let Some(anchor) = self.view.selection_anchor {
+            if anchor.line > last_line || anchor.grapheme > self.line_grapheme_count(anchor.line) + 1 {
+                self.view.selection_anchor = None;
+            }
+        }
+    }
+
+    pub (super) fn focus_window(&mut self, window: usize) {
+        if window == self.active_window || window >= self.windows.len() {
+            return;
+        }
+        if self.mode == EditorMode::Visual {
+            self.mode = EditorMode::Normal;
+            self.clear_selection();
+        }
+        self.doc.history.seal();
+        self.swap_in_window(window);
+        self.clamp_cursor_to_line();
+    }
+
+    // Ctrl+W h, j, k and l, the nearest pane on that side of the focused one.
+    fn focus_neighbour(&mut self, side: char) {
+        let focused = match self.panes.iter().find(|pane| pane.window == self.active_window) {
+            Some(pane) => *pane,
+            None => return,
+        };
+        let (x, y) = (focused.at.0 as i32, focused.at.1 as i32);
+        let (w, h) = (focused.size.0 as i32, focused.size.1 as i32);
+        let neighbour = self.panes.iter()
+            .filter(|pane| {
+                let (px, py) = (pane.at.0 as i32, pane.at.1 as i32);
+                let (pw, ph) = (pane.size.0 as i32, pane.size.1 as i32);
+                let overlaps_rows = py < y + h && y < py + ph;
+                let overlaps_columns = px < x + w && x < px + pw;
+                match side {
+                    'h' => px + pw <= x && overlaps_rows,
+                    'l' => px >= x + w && overlaps_rows,
+                    'k' => py + ph <= y && overlaps_columns,
+                    _ => py >= y + h && overlaps_columns,
+                }
+            })
+            .min_by_key(|pane| {
+                let (px, py) = (pane.at.0 as i32, pane.at.1 as i32);
+                (px - x).abs() + (py - y).abs()
+            })
+            .map(|pane| pane.window);
+        if let Some(window) = neighbour {
+            self.focus_window(window);
+        }
+    }
+
+    // Ctrl+W w and W, the next (or previous) window in screen order.
+    fn cycle_window(&mut self, forward: bool) {
+        let windows = self.layout.windows();
+        let position = windows.iter().position(|window| *window == self.active_window).unwrap_or(0);
+        let offset = if forward {1} else {windows.len() - 1};
+        self.focus_window(windows[(position + offset) % windows.len()]);
+    }
+
+    // Shows the focused document in a new window next to the focused one,
+    // or `path` when given.
+    pub (super) fn split_window(&mut self, direction: Direction, path: Option<String>) {
+        self.windows.push(Window {
+            document: self.active_document,
+            view: self.view.clone(),
+            text_size: self.text_size,
+        });
+        let window = self.windows.len() - 1;
+        self.layout.split(self.active_window, window, direction);
+        self.focus_window(window);
+        if let Some(path) = path {
+            self.open(path);
+        }
+    }
+
+    pub (super) fn close_window(&mut self) -> Result<(), String> {
+        let closing = self.active_window;
+        let next = self.layout.remove(closing).ok_or("Cannot close the last window")?;
+        self.focus_window(next);
+        self.windows.remove(closing);
+        self.layout.renumber(closing);
+        if self.active_window > closing {
+            self.active_window -= 1;
+        }
+        Ok(())
+    }
+
+    // Closes every window but the focused one.
+    pub (super) fn only_window(&mut self) {
+        let focused = self.windows.swap_remove(self.active_window);
+        self.windows = vec![focused];
+        self.active_window = 0;
+        self.layout = Layout::Window(0);
+    }
+
+    // Keeps the windows showing documents in step with the list after
+    // `closed` was removed from it, showing `replacement` instead.
+    pub (super) fn forget_document(&mut self, closed: usize, replacement: usize) {
+        for window in self.windows.iter_mut() {
+            if window.document == closed {
+                window.document = replacement;
+                window.view = View::default();
+            } else if window.document > closed {
+                window.document -= 1;
+            }
+        }
+    }
+
+    pub (super) fn window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    // The key after Ctrl+W.
+    pub (super) fn process_window_key(&mut self, key_event: event::KeyEvent) {
+        let key = match key_event.code {
+            event::KeyCode::Char(c) => c,
+            event::KeyCode::Left => 'h',
+            event::KeyCode::Down => 'j',
+            event::KeyCode::Up => 'k',
+            event::KeyCode::Right => 'l',
+            _ => return,
+        };
+        match key {
+            's' => self.split_window(Direction::Horizontal, None),
+            'v' => self.split_window(Direction::Vertical, None),
+            'q' | 'c' => {
+                if let Err(e) = self.close_window() {
+                    self.header_msg = e;
+                }
+            }
+            'o' => self.only_window(),
+            'h' | 'j' | 'k' | 'l' => self.focus_neighbour(key),
+            'w' => self.cycle_window(true),
+            'W' => self.cycle_window(false),
+            '+' => {self.layout.resize(self.active_window, Direction::Horizontal, 0.05);}
+            '-' => {self.layout.resize(self.active_window, Direction::Horizontal, -0.05);}
+            '>' => {self.layout.resize(self.active_window, Direction::Vertical, 0.05);}
+            '<' => {self.layout.resize(self.active_window, Direction::Vertical, -0.05);}
+            '=' => self.layout.equalize(),
+            _ => {}
+        }
+    }
+
+}
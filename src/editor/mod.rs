@@ -2,104 +2,235 @@
 use syntect::{parsing::SyntaxSet, highlighting::{ThemeSet}};
 
 
+mod buffer;
+mod position;
+mod history;
+mod encoding;
+mod save;
+mod document;
+mod layout;
+mod highlight;
+#[cfg(feature = "treesitter")]
+mod treesitter;
 mod cursor;
+mod selection;
+mod indent;
+mod motion;
+mod normal;
+mod clipboard;
+mod command;
+mod search;
+mod replace;
 mod event;
+mod screen;
+mod wrap;
 mod draw;
-
-#[derive(Debug)]
+#[cfg(test)]
+mod tests;
+
+use document::{Document, View};
+use layout::{Layout, Pane, Window};
+use screen::Screen;
+use highlight::HighlightWorker;
+use clipboard::Clipboard;
+use normal::PendingCommand;
+use command::CommandLine;
+use search::Search;
+use replace::Replace;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EditorMode {
+    Normal,
     Insert,
+    Visual,
+    Command,
+    Search,
+    Confirm,
+}
+
+impl std::fmt::Display for EditorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EditorMode::Normal => "NORMAL",
+            EditorMode::Insert => "INSERT",
+            EditorMode::Visual => "VISUAL",
+            EditorMode::Command => "COMMAND",
+            EditorMode::Search => "SEARCH",
+            EditorMode::Confirm => "CONFIRM",
+        };
+        write!(f, "{}", name)
+    }
 }
+
 #[derive(Debug)]
 pub struct Editor {
 
     mode: EditorMode,
+    pending: PendingCommand,
+    command_line: CommandLine,
+    search: Search,
+    replace: Option<Replace>,
 
-    current: bool,
-    path: String,
+    quit_armed: bool,
     pub header_msg: String,
 
     pub theme_name: String,
 
-    lines: Vec<String>,
+    doc: Document,
+    documents: Vec<Document>,
+    active_document: usize,
+
+    windows: Vec<Window>,
+    active_window: usize,
+    layout: Layout,
+    panes: Vec<Pane>,
+    window_prefix: bool,
+    screen: Screen,
+
+    // Files are read in this encoding rather than the one they appear to be in.
+    encoding: Option<&'static encoding_rs::Encoding>,
+    // Saving keeps a copy of the previous version, next to the file or in
+    // `backup_dir` when it is set.
+    backup: bool,
+    backup_dir: String,
 
     text_size: (usize, usize),
-    display_top_line_index: usize,
-    display_rightmost_index: usize,
+    view: View,
     display_shifted_by_cursor: bool,
+    soft_wrap: bool,
+    // Soft wrap breaks lines after blanks where it can.
+    wrap_at_words: bool,
 
-    cursor_line_index: usize,
-    cursor_index: usize,
-    cursor_prefered_index: usize,
+    pub clipboard: Clipboard,
 
     show_cursor: bool,
 
-    use_crlf: bool,
-
-    num_spaces_per_tab: u8,
+    tab_width: u8,
+    // The Tab key inserts spaces rather than a tab character.
+    expand_tab: bool,
 
     pub syntax_set: SyntaxSet,
     pub theme_set: ThemeSet,
     use_theme_background: bool,
+    highlight_worker: HighlightWorker,
+    // Languages highlighted with tree-sitter rather than syntect.
+    tree_sitter_languages: Vec<String>,
 }
 
 impl Default for Editor {
     fn default() -> Self {
         Self {
             mode: EditorMode::Insert,
+            pending: PendingCommand::default(),
+            command_line: CommandLine::default(),
+            search: Search::default(),
+            replace: None,
             
-            current: false,
-            path: "Untitled".to_string(),
+            quit_armed: false,
             header_msg: "".to_string(),
 
             theme_name: "".to_string(),
 
-            lines: Vec::<String>::new(),
+            doc: Document::untitled(),
+            documents: vec![Document::default()],
+            active_document: 0,
             
+            windows: vec![Window::default()],
+            active_window: 0,
+            layout: Layout::Window(0),
+            panes: Vec::new(),
+            window_prefix: false,
+            screen: Screen::default(),
+
+            encoding: None,
+            backup: false,
+            backup_dir: String::new(),
+
             text_size: (0,0),
-            display_top_line_index: 0,
-            display_rightmost_index: 0,
+            view: View::default(),
             display_shifted_by_cursor: true,
+            soft_wrap: false,
+            wrap_at_words: false,
 
-            cursor_line_index: 0,
-            cursor_index: 0,
-            cursor_prefered_index: 0,
+            clipboard: Clipboard::default(),
 
             show_cursor: true,
 
-            use_crlf: true,
-            num_spaces_per_tab: 4,
+            tab_width: 4,
+            expand_tab: true,
 
             syntax_set: SyntaxSet::load_defaults_nonewlines(),
             theme_set: ThemeSet::load_defaults(),
             use_theme_background: true,
+            highlight_worker: HighlightWorker::default(),
+            tree_sitter_languages: highlight::tree_sitter_grammars().into_iter().map(String::from).collect(),
         }
     }
 }
 
 impl Editor {
-    pub fn new(path: String) -> Self {
-        let s = std::fs::read_to_string(&path).unwrap_or(String::from(""));
-        let lines = s.lines().map(|s| s.to_owned() ).collect::<Vec<_>>();
-        let lines = if !lines.is_empty() {lines} else {vec!["".to_string()]};
-        Self {
-            path,
-            lines,
-            ..Default::default()
+    pub fn new(paths: Vec<String>, encoding: Option<&'static encoding_rs::Encoding>) -> Self {
+        let mut editor = Self { encoding, ..Self::default() };
+        let mut documents: Vec<Document> = paths.into_iter().map(|path| Document::open(path, encoding)).collect();
+        if !documents.is_empty() {
+            editor.doc = std::mem::take(&mut documents[0]);
+            editor.documents = documents;
         }
+        editor.report_loaded_document();
+        editor
     }
 
-    fn save(&mut self) -> Result<(), std::io::Error> {
-        let content = self.lines.iter().map(|l|l.clone()).intersperse(if self.use_crlf {"\r\n"} else {"\n"}.to_string()).collect::<String>();
-        std::fs::write(&self.path, content.as_bytes())?;
-        self.current = true;
+    // Writes the document to `path`, which only counts as saving it when
+    // that is its own file or it has none yet.
+    fn save(&mut self, path: &str) -> Result<(), std::io::Error> {
+        let encoding = self.doc.encoding.name();
+        let own_file = path == self.doc.path
+            || std::fs::canonicalize(path).ok().is_some_and(|path| std::fs::canonicalize(&self.doc.path).ok() == Some(path));
+        if own_file {
+            // The buffer is empty rather than the file's text, whatever `readonly` says.
+            if let document::OpenStatus::Unreadable(e) = &self.doc.status {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("the file could not be read ({}), saving would empty it", e)));
+            }
+            if self.doc.read_only {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "the file is read-only, :set noreadonly to write it anyway"));
+            }
+        }
+        if self.doc.undecodable {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("the file is not valid {}", encoding)));
+        }
+        let mut text = Vec::with_capacity(self.doc.buffer.len_chars());
+        let line_ending = self.doc.line_ending.as_str();
+        self.doc.buffer.write_to(&mut text, line_ending)?;
+        if self.doc.final_newline {
+            text.extend_from_slice(line_ending.as_bytes());
+        }
+        let text = String::from_utf8(text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let content = self.doc.encoding.encode(&text).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("the text has characters {} cannot encode", encoding))
+        })?;
+        if self.backup {
+            save::back_up(std::path::Path::new(path), &self.backup_dir)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("could not back it up: {}", e)))?;
+        }
+        save::write_atomically(std::path::Path::new(path), &content)?;
+        if self.doc.status == document::OpenStatus::Untitled {
+            self.doc.path = path.to_string();
+            self.doc.status = document::OpenStatus::Loaded;
+            self.doc.syntax_name = Default::default();
+        } else if !own_file {
+            return Ok(());
+        }
+        self.doc.mixed_line_endings = false;
+        self.doc.history.mark_saved(&content);
+        self.doc.current = true;
+        self.store_history();
         Ok(())
     }
 
-    fn title(&self) -> String {
-        let mut ret = if self.current {String::new()} else {"*".to_string()};
-        ret.push_str(&self.path);
-        ret
+    fn store_history(&mut self) {
+        if let Err(e) = self.doc.history.store(&self.doc.path) {
+            self.header_msg = format!("Could not store undo history: {}", e);
+        }
     }
 
 }
@@ -0,0 +1,213 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::Editor;
+use super::position::Position;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+// Part of the buffer an operator acts on.
+// `end` is exclusive for every kind, linewise ranges cover whole lines.
+#[derive(Debug, Clone, Copy)]
+pub struct TextRange {
+    pub start: Position,
+    pub end: Position,
+    pub kind: MotionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class_of(grapheme: &str) -> CharClass {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => CharClass::Blank,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        Some(_) => CharClass::Punctuation,
+        None => CharClass::Blank,
+    }
+}
+
+// Steps through the buffer one grapheme at a time, the position one past the
+// end of a line stands for its newline and counts as blank.
+struct ClassWalker<'a> {
+    editor: &'a Editor,
+    pos: Position,
+    classes: Vec<CharClass>,
+}
+
+impl<'a> ClassWalker<'a> {
+    fn new(editor: &'a Editor, pos: Position) -> Self {
+        let mut walker = Self { editor, pos, classes: Vec::new() };
+        walker.load_line();
+        walker
+    }
+
+    fn load_line(&mut self) {
+        self.classes = self.editor.line_at_index(self.pos.line)
+            .map(|line| line.graphemes(true).map(class_of).collect())
+            .unwrap_or_default();
+    }
+
+    fn class(&self) -> CharClass {
+        self.classes.get(self.pos.grapheme).copied().unwrap_or(CharClass::Blank)
+    }
+
+    fn next_class(&self) -> CharClass {
+        self.classes.get(self.pos.grapheme + 1).copied().unwrap_or(CharClass::Blank)
+    }
+
+    fn on_empty_line(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    fn forward(&mut self) -> bool {
+        if self.pos.grapheme < self.classes.len() {
+            self.pos.grapheme += 1;
+        } else if self.pos.line + 1 < self.editor.doc.buffer.len_lines() {
+            self.pos = Position::new(self.pos.line + 1, 0);
+            self.load_line();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn backward(&mut self) -> bool {
+        if self.pos.grapheme > 0 {
+            self.pos.grapheme -= 1;
+        } else if self.pos.line > 0 {
+            self.pos.line -= 1;
+            self.load_line();
+            self.pos.grapheme = self.classes.len();
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+impl Editor {
+
+    pub (super) fn line_grapheme_count(&self, line_index: usize) -> usize {
+        self.line_at_index(line_index).map_or(0, |line| line.graphemes(true).count())
+    }
+
+    // Position of the grapheme following `pos`, stepping over the newline.
+    pub (super) fn position_after(&self, pos: Position) -> Position {
+        if pos.grapheme < self.line_grapheme_count(pos.line) {
+            Position::new(pos.line, pos.grapheme + 1)
+        } else if pos.line + 1 < self.doc.buffer.len_lines() {
+            Position::new(pos.line + 1, 0)
+        } else {
+            pos
+        }
+    }
+
+    pub (super) fn first_non_blank(&self, line_index: usize) -> usize {
+        self.line_at_index(line_index).map_or(0, |line| {
+            line.graphemes(true).take_while(|g| class_of(g) == CharClass::Blank).count()
+        })
+    }
+
+    pub (super) fn next_word_start(&self, from: Position) -> Position {
+        let mut walker = ClassWalker::new(self, from);
+        let start_class = walker.class();
+        if start_class != CharClass::Blank {
+            while walker.class() == start_class && walker.forward() {}
+        }
+        while walker.class() == CharClass::Blank {
+            if walker.on_empty_line() && walker.pos != from {
+                break;
+            }
+            if !walker.forward() {
+                break;
+            }
+        }
+        walker.pos
+    }
+
+    pub (super) fn next_word_end(&self, from: Position) -> Position {
+        let mut walker = ClassWalker::new(self, from);
+        walker.forward();
+        while walker.class() == CharClass::Blank && walker.forward() {}
+        let class = walker.class();
+        while class != CharClass::Blank && walker.next_class() == class {
+            walker.forward();
+        }
+        walker.pos
+    }
+
+    pub (super) fn previous_word_start(&self, from: Position) -> Position {
+        let mut walker = ClassWalker::new(self, from);
+        walker.backward();
+        while walker.class() == CharClass::Blank && !walker.on_empty_line() && walker.backward() {}
+        let class = walker.class();
+        while class != CharClass::Blank && walker.pos.grapheme > 0 && walker.classes[walker.pos.grapheme - 1] == class {
+            walker.pos.grapheme -= 1;
+        }
+        walker.pos
+    }
+
+    // `iw` and `aw`, the run of same-class graphemes under the cursor, `aw`
+    // also takes the blanks after it (or before it when there are none after).
+    pub (super) fn word_object(&self, around: bool) -> Option<TextRange> {
+        let Position { line: line_index, grapheme: index } = self.cursor_position();
+        let classes: Vec<CharClass> = self.line_at_index(line_index)?.graphemes(true).map(class_of).collect();
+        let class = *classes.get(index)?;
+        let mut start = index;
+        while start > 0 && classes[start - 1] == class {start -= 1;}
+        let mut end = index + 1;
+        while end < classes.len() && classes[end] == class {end += 1;}
+        if around && class != CharClass::Blank {
+            let trailing_end = (end..classes.len()).find(|i| classes[*i] != CharClass::Blank).unwrap_or(classes.len());
+            if trailing_end > end {
+                end = trailing_end;
+            } else {
+                while start > 0 && classes[start - 1] == CharClass::Blank {start -= 1;}
+            }
+        }
+        Some(TextRange { start: Position::new(line_index, start), end: Position::new(line_index, end), kind: MotionKind::Exclusive })
+    }
+
+    // `i"`, `a"` and friends, quotes on the cursor line are paired up from the
+    // start of the line and the pair around (or after) the cursor is used.
+    pub (super) fn quote_object(&self, quote: char, around: bool) -> Option<TextRange> {
+        let Position { line: line_index, grapheme: index } = self.cursor_position();
+        let line = self.line_at_index(line_index)?;
+        let quotes: Vec<usize> = line.graphemes(true)
+            .enumerate()
+            .filter(|(_, g)| g.chars().eq(std::iter::once(quote)))
+            .map(|(i, _)| i)
+            .collect();
+        let (open, close) = quotes.chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|(_, close)| *close >= index)?;
+        let range = if around {(open, close + 1)} else {(open + 1, close)};
+        Some(TextRange { start: Position::new(line_index, range.0), end: Position::new(line_index, range.1), kind: MotionKind::Exclusive })
+    }
+
+    // `ip` and `ap`, the block of blank or non-blank lines around the cursor,
+    // `ap` also takes the blank lines that follow it.
+    pub (super) fn paragraph_object(&self, around: bool) -> Option<TextRange> {
+        let is_blank = |l: usize| self.line_at_index(l).is_none_or(|line| line.trim().is_empty());
+        let line_count = self.doc.buffer.len_lines();
+        let blank = is_blank(self.view.cursor.line);
+        let mut first = self.view.cursor.line;
+        while first > 0 && is_blank(first - 1) == blank {first -= 1;}
+        let mut last = self.view.cursor.line;
+        while last + 1 < line_count && is_blank(last + 1) == blank {last += 1;}
+        if around && !blank {
+            while last + 1 < line_count && is_blank(last + 1) {last += 1;}
+        }
+        Some(TextRange { start: Position::new(first, 0), end: Position::new(last, self.line_grapheme_count(last)), kind: MotionKind::Linewise })
+    }
+
+}
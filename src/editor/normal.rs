@@ -0,0 +1,524 @@
+use crossterm::event;
+
+use super::Editor;
+use super::EditorMode;
+use super::clipboard::{self, Register};
+use super::history::EditKind;
+use super::motion::{MotionKind, TextRange};
+use super::position::Position;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+// Keys of a normal mode command typed so far, e.g. `2d3` of `2d3w`.
+#[derive(Debug, Default)]
+pub struct PendingCommand {
+    count: Option<usize>,
+    operator: Option<(Operator, usize)>,
+    prefix: Option<char>,
+}
+
+impl PendingCommand {
+    fn take_count(&mut self) -> usize {
+        self.count.take().unwrap_or(1)
+    }
+}
+
+impl Editor {
+
+    pub (super) fn enter_normal_mode(&mut self) {
+        if self.mode == EditorMode::Insert && self.view.cursor.grapheme > 0 {
+            self.view.cursor.grapheme -= 1;
+        }
+        self.mode = EditorMode::Normal;
+        self.pending = PendingCommand::default();
+        self.clear_selection();
+        self.doc.history.seal();
+        self.clamp_cursor_to_line();
+    }
+
+    pub (super) fn enter_insert_mode(&mut self) {
+        self.mode = EditorMode::Insert;
+        self.pending = PendingCommand::default();
+        self.clear_selection();
+        self.doc.history.seal();
+    }
+
+    fn enter_visual_mode(&mut self) {
+        self.mode = EditorMode::Visual;
+        self.view.selection_anchor = Some(self.cursor_position());
+    }
+
+    // Outside of insert mode the cursor sits on a grapheme, never past the
+    // end of a non-empty line.
+    pub (super) fn clamp_cursor_to_line(&mut self) {
+        let len = self.line_grapheme_count(self.view.cursor.line);
+        if self.mode != EditorMode::Insert && len > 0 && self.view.cursor.grapheme >= len {
+            self.view.cursor.grapheme = len - 1;
+        }
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
+    }
+
+    pub (super) fn process_normal_key(&mut self, key_event: event::KeyEvent) -> Result<bool, Box<dyn std::error::Error>> {
+        let quit_armed = std::mem::take(&mut self.quit_armed);
+        if key_event.modifiers.contains(event::KeyModifiers::CONTROL) {
+            match key_event.code {
+                event::KeyCode::Char('q') => {
+                    return Ok(!self.request_quit(quit_armed));
+                }
+                event::KeyCode::Char('s') => {
+                    if let Err(e) = self.write_to("") {
+                        self.header_msg = e;
+                    }
+                }
+                event::KeyCode::Char('f') => {
+                    self.enter_search_mode(false);
+                }
+                event::KeyCode::Char('h') => {
+                    self.open_replace_prompt();
+                }
+                event::KeyCode::Char('w') => {
+                    self.window_prefix = true;
+                }
+                event::KeyCode::Char('l') => {
+                    self.screen.invalidate();
+                }
+                event::KeyCode::Tab | event::KeyCode::PageDown => {
+                    self.cycle_document(true);
+                }
+                event::KeyCode::BackTab | event::KeyCode::PageUp => {
+                    self.cycle_document(false);
+                }
+                event::KeyCode::Char('r') => {
+                    for _ in 0..self.pending.take_count() {
+                        self.redo();
+                    }
+                    self.clamp_cursor_to_line();
+                }
+
+                _ => {}
+            }
+            self.pending = PendingCommand::default();
+            return Ok(true);
+        }
+        match key_event.code {
+            event::KeyCode::Esc => {
+                self.enter_normal_mode();
+            }
+            event::KeyCode::Char(c) => {
+                self.process_normal_char(c);
+            }
+            event::KeyCode::Left | event::KeyCode::Backspace => {
+                self.process_normal_char('h');
+            }
+            event::KeyCode::Right => {
+                self.process_normal_char('l');
+            }
+            event::KeyCode::Up => {
+                self.process_normal_char('k');
+            }
+            event::KeyCode::Down => {
+                self.process_normal_char('j');
+            }
+            event::KeyCode::Home => {
+                self.process_normal_char('0');
+            }
+            event::KeyCode::End => {
+                self.process_normal_char('$');
+            }
+            event::KeyCode::F(3) => {
+                self.search_next(key_event.modifiers.contains(event::KeyModifiers::SHIFT));
+            }
+
+            _ => {}
+        }
+        if self.mode != EditorMode::Insert {
+            self.clamp_cursor_to_line();
+        }
+        Ok(true)
+    }
+
+    fn process_normal_char(&mut self, c: char) {
+        if let Some(prefix) = self.pending.prefix.take() {
+            match (prefix, c) {
+                ('g', 'g') => {
+                    let last_line = self.doc.buffer.len_lines() - 1;
+                    let line = self.pending.count.take().map_or(0, |line| line.clamp(1, last_line + 1) - 1);
+                    self.run_motion(Position::new(line, 0), MotionKind::Linewise);
+                }
+                ('i', _) | ('a', _) => {
+                    let range = match c {
+                        'w' => self.word_object(prefix == 'a'),
+                        'p' => self.paragraph_object(prefix == 'a'),
+                        '"' | '\'' | '`' => self.quote_object(c, prefix == 'a'),
+                        _ => None,
+                    };
+                    match range {
+                        Some(range) => self.run_range(range),
+                        None => self.pending = PendingCommand::default(),
+                    }
+                }
+                _ => self.pending = PendingCommand::default(),
+            }
+            return;
+        }
+
+        if let Some(digit) = c.to_digit(10) {
+            if digit != 0 || self.pending.count.is_some() {
+                self.pending.count = Some(self.pending.count.unwrap_or(0) * 10 + digit as usize);
+                return;
+            }
+        }
+
+        let selecting = self.mode == EditorMode::Visual;
+        let operating = self.pending.operator.is_some();
+        match c {
+            'G' => {
+                let last_line = self.doc.buffer.len_lines() - 1;
+                let line = self.pending.count.take().map_or(last_line, |line| line.clamp(1, last_line + 1) - 1);
+                self.run_motion(Position::new(line, 0), MotionKind::Linewise);
+            }
+            'h' | 'l' | 'j' | 'k' | 'w' | 'b' | 'e' | '0' | '^' | '$' => {
+                let count = self.pending.take_count() * self.pending.operator.map_or(1, |(_, count)| count);
+                self.run_motion_key(c, count);
+            }
+            'g' => {
+                self.pending.prefix = Some('g');
+            }
+            'i' | 'a' if selecting || operating => {
+                self.pending.prefix = Some(c);
+            }
+            'd' | 'c' | 'y' | 'x' | 'D' | 'C' | 'Y' | 'X' | 's' if selecting => {
+                let operator = match c {
+                    'd' | 'x' | 'D' | 'X' => Operator::Delete,
+                    'c' | 'C' | 's' => Operator::Change,
+                    _ => Operator::Yank,
+                };
+                let anchor = self.view.selection_anchor.unwrap_or(self.cursor_position());
+                if c.is_ascii_uppercase() {
+                    let first = anchor.line.min(self.view.cursor.line);
+                    let last = anchor.line.max(self.view.cursor.line);
+                    let range = TextRange { start: Position::new(first, 0), end: Position::new(last, self.line_grapheme_count(last)), kind: MotionKind::Linewise };
+                    self.apply_operator(operator, range);
+                } else if let Some((start, end)) = self.selection_range() {
+                    self.apply_operator(operator, TextRange { start, end, kind: MotionKind::Exclusive });
+                }
+                if self.mode == EditorMode::Visual {
+                    self.enter_normal_mode();
+                }
+            }
+            'd' | 'c' | 'y' => {
+                let operator = match c {
+                    'd' => Operator::Delete,
+                    'c' => Operator::Change,
+                    _ => Operator::Yank,
+                };
+                let count = self.pending.take_count();
+                match self.pending.operator {
+                    Some((pending, pending_count)) if pending == operator => {
+                        // `dd`, `cc` and `yy` act on whole lines.
+                        let last = (self.view.cursor.line + count * pending_count - 1).min(self.doc.buffer.len_lines() - 1);
+                        self.run_range(TextRange {
+                            start: Position::new(self.view.cursor.line, 0),
+                            end: Position::new(last, self.line_grapheme_count(last)),
+                            kind: MotionKind::Linewise,
+                        });
+                    }
+                    Some(_) => self.pending = PendingCommand::default(),
+                    None => self.pending.operator = Some((operator, count)),
+                }
+            }
+            'x' | 'X' | 'D' | 'C' | 's' | 'Y' if !operating => {
+                let (operator, motion) = match c {
+                    'x' => (Operator::Delete, 'l'),
+                    'X' => (Operator::Delete, 'h'),
+                    'D' => (Operator::Delete, '$'),
+                    'C' => (Operator::Change, '$'),
+                    's' => (Operator::Change, 'l'),
+                    _ => (Operator::Yank, 'y'),
+                };
+                if motion == 'y' {
+                    self.process_normal_char('y');
+                    self.process_normal_char('y');
+                } else {
+                    self.pending.operator = Some((operator, 1));
+                    self.process_normal_char(motion);
+                }
+            }
+            'p' | 'P' if !operating => {
+                let count = self.pending.take_count();
+                self.doc.history.begin_group();
+                if selecting {
+                    self.delete_selection();
+                    self.enter_normal_mode();
+                }
+                self.put_register(clipboard::DEFAULT_REGISTER, c == 'p', count);
+                self.doc.history.end_group();
+            }
+            'u' if !operating && !selecting => {
+                for _ in 0..self.pending.take_count() {
+                    self.undo();
+                }
+            }
+            'o' if selecting => {
+                let anchor = self.view.selection_anchor.replace(self.cursor_position()).unwrap();
+                self.set_cursor_position(anchor);
+            }
+            'i' | 'a' | 'I' | 'A' | 'o' | 'O' if !operating => {
+                self.pending = PendingCommand::default();
+                self.enter_insert_mode();
+                match c {
+                    'a' => {
+                        let len = self.line_grapheme_count(self.view.cursor.line);
+                        self.set_cursor_position(Position::new(self.view.cursor.line, (self.view.cursor.grapheme + 1).min(len)));
+                    }
+                    'I' => self.set_cursor_position(Position::new(self.view.cursor.line, self.first_non_blank(self.view.cursor.line))),
+                    'A' => self.move_cursor_to_end_of_line(),
+                    'o' => {
+                        self.move_cursor_to_end_of_line();
+                        self.linesplit_at_cursor();
+                    }
+                    'O' => self.open_line_above(),
+                    _ => {}
+                }
+            }
+            '/' | '?' if !operating => {
+                self.pending = PendingCommand::default();
+                self.enter_search_mode(c == '?');
+            }
+            'n' | 'N' if !operating => {
+                for _ in 0..self.pending.take_count() {
+                    self.search_next(c == 'N');
+                }
+            }
+            ':' if !operating => {
+                self.pending = PendingCommand::default();
+                self.enter_command_mode("");
+            }
+            'v' if !operating => {
+                if selecting {
+                    self.enter_normal_mode();
+                } else {
+                    self.enter_visual_mode();
+                }
+            }
+
+            _ => self.pending = PendingCommand::default(),
+        }
+    }
+
+    fn run_motion_key(&mut self, key: char, count: usize) {
+        let Position { line: line_index, grapheme: index } = self.cursor_position();
+        let last_line = self.doc.buffer.len_lines() - 1;
+        let (target, kind) = match key {
+            'h' => (Position::new(line_index, index.saturating_sub(count)), MotionKind::Exclusive),
+            'l' => {
+                let len = self.line_grapheme_count(line_index);
+                (Position::new(line_index, (index + count).min(len)), MotionKind::Exclusive)
+            }
+            'j' | 'k' if self.pending.operator.is_none() => {
+                for _ in 0..count {
+                    if key == 'j' {self.move_cursor_down();} else {self.move_cursor_up();}
+                }
+                return;
+            }
+            'j' => (Position::new((line_index + count).min(last_line), 0), MotionKind::Linewise),
+            'k' => (Position::new(line_index.saturating_sub(count), 0), MotionKind::Linewise),
+            '0' => (Position::new(line_index, 0), MotionKind::Exclusive),
+            '^' => (Position::new(line_index, self.first_non_blank(line_index)), MotionKind::Exclusive),
+            '$' => {
+                let line = (line_index + count - 1).min(last_line);
+                match self.line_grapheme_count(line) {
+                    // Nothing to include, the line break stays out of it.
+                    0 => (Position::new(line, 0), MotionKind::Exclusive),
+                    len => (Position::new(line, len - 1), MotionKind::Inclusive),
+                }
+            }
+            'w' => {
+                let mut target = self.cursor_position();
+                for _ in 0..count {
+                    target = self.next_word_start(target);
+                }
+                if matches!(self.pending.operator, Some((Operator::Change, _))) {
+                    // `cw` behaves like `ce` when started on a word.
+                    target = self.cursor_position();
+                    for _ in 0..count {
+                        target = self.next_word_end(target);
+                    }
+                    (target, MotionKind::Inclusive)
+                } else if self.pending.operator.is_some() && target.line > line_index
+                    && target != Position::new(last_line, self.line_grapheme_count(last_line))
+                {
+                    // An operator stops at the end of the line the last word
+                    // was on rather than taking the line break along.
+                    let line = target.line - 1;
+                    (Position::new(line, self.line_grapheme_count(line)), MotionKind::Exclusive)
+                } else {
+                    (target, MotionKind::Exclusive)
+                }
+            }
+            'b' => {
+                let mut target = self.cursor_position();
+                for _ in 0..count {
+                    target = self.previous_word_start(target);
+                }
+                (target, MotionKind::Exclusive)
+            }
+            'e' => {
+                let mut target = self.cursor_position();
+                for _ in 0..count {
+                    target = self.next_word_end(target);
+                }
+                (target, MotionKind::Inclusive)
+            }
+            _ => return,
+        };
+        self.run_motion(target, kind);
+    }
+
+    // Moves the cursor to `target`, or applies the pending operator to the
+    // text between the cursor and `target`.
+    fn run_motion(&mut self, target: Position, kind: MotionKind) {
+        if self.pending.operator.is_none() {
+            self.pending = PendingCommand::default();
+            let index = if kind == MotionKind::Linewise {self.first_non_blank(target.line)} else {target.grapheme};
+            self.set_cursor_position(Position::new(target.line, index));
+            return;
+        }
+        let cursor = self.cursor_position();
+        let (start, end) = if target < cursor {(target, cursor)} else {(cursor, target)};
+        let range = match kind {
+            MotionKind::Linewise => TextRange {
+                start: Position::new(start.line, 0),
+                end: Position::new(end.line, self.line_grapheme_count(end.line)),
+                kind,
+            },
+            MotionKind::Inclusive => TextRange { start, end: self.position_after(end), kind },
+            MotionKind::Exclusive => TextRange { start, end, kind },
+        };
+        self.run_range(range);
+    }
+
+    fn run_range(&mut self, range: TextRange) {
+        let operator = self.pending.operator.map(|(operator, _)| operator);
+        self.pending = PendingCommand::default();
+        match operator {
+            Some(operator) => self.apply_operator(operator, range),
+            None if self.mode == EditorMode::Visual => {
+                // A text object in visual mode selects it.
+                self.view.selection_anchor = Some(range.start);
+                let end = if range.kind == MotionKind::Linewise {
+                    self.line_grapheme_count(range.end.line).saturating_sub(1)
+                } else {
+                    range.end.grapheme.saturating_sub(1)
+                };
+                self.set_cursor_position(Position::new(range.end.line, end));
+            }
+            None => {}
+        }
+    }
+
+    // Char positions of the text `range` covers, linewise ranges take a line
+    // break with them so that the lines disappear entirely.
+    fn range_char_bounds(&self, range: TextRange) -> ((usize, usize), (usize, usize)) {
+        let start = self.char_position(range.start).unwrap();
+        let end = self.char_position(range.end).unwrap();
+        if range.kind != MotionKind::Linewise {
+            (start, end)
+        } else if range.end.line + 1 < self.doc.buffer.len_lines() {
+            ((range.start.line, 0), (range.end.line + 1, 0))
+        } else if range.start.line > 0 {
+            let previous = range.start.line - 1;
+            ((previous, self.doc.buffer.line(previous).unwrap().chars().count()), end)
+        } else {
+            ((0, 0), end)
+        }
+    }
+
+    fn apply_operator(&mut self, operator: Operator, range: TextRange) {
+        let linewise = range.kind == MotionKind::Linewise;
+        let (from, to) = self.range_char_bounds(range);
+        // Nothing to take, the register keeps what it had.
+        if from == to && !linewise {
+            if operator == Operator::Change {
+                self.set_cursor_position(range.start);
+                self.enter_insert_mode();
+            }
+            return;
+        }
+        let mut text = self.doc.buffer.text_between(from, to);
+        if linewise {
+            // Registers hold whole lines as "line\n" whichever line break
+            // was taken along.
+            if from.0 < range.start.line {
+                text.remove(0);
+            }
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        self.clipboard.set(clipboard::DEFAULT_REGISTER, text, linewise);
+
+        match operator {
+            Operator::Yank => {
+                self.set_cursor_position(range.start);
+            }
+            Operator::Delete => {
+                self.remove_text(from, to, EditKind::Other);
+                if linewise {
+                    let line = range.start.line.min(self.doc.buffer.len_lines() - 1);
+                    self.set_cursor_position(Position::new(line, self.first_non_blank(line)));
+                } else {
+                    self.set_cursor_position(range.start);
+                }
+            }
+            Operator::Change => {
+                // Removed as the start of an insertion, the text typed next
+                // undoes along with it.
+                self.enter_insert_mode();
+                if linewise {
+                    // Keep one (empty) line to type into.
+                    let end = (range.end.line, self.doc.buffer.line(range.end.line).unwrap().chars().count());
+                    self.remove_text((range.start.line, 0), end, EditKind::Insert);
+                } else {
+                    self.remove_text(from, to, EditKind::Insert);
+                }
+                self.set_cursor_position(Position::new(range.start.line, if linewise {0} else {range.start.grapheme}));
+            }
+        }
+    }
+
+    // `p` and `P`, linewise registers go below or above the cursor line.
+    fn put_register(&mut self, register: char, after: bool, count: usize) {
+        let Register { text, linewise } = match self.clipboard.get(register) {
+            Some(register) => register.clone(),
+            None => return,
+        };
+        let text = text.repeat(count);
+        let Position { line: line_index, grapheme: index } = self.cursor_position();
+        if linewise {
+            let (at, inserted, line) = if !after {
+                ((line_index, 0), text, line_index)
+            } else if line_index + 1 < self.doc.buffer.len_lines() {
+                ((line_index + 1, 0), text, line_index + 1)
+            } else {
+                let end = self.doc.buffer.line(line_index).unwrap().chars().count();
+                ((line_index, end), format!("\n{}", text.strip_suffix('\n').unwrap_or(&text)), line_index + 1)
+            };
+            self.insert_text(at, &inserted, EditKind::Other);
+            self.set_cursor_position(Position::new(line, self.first_non_blank(line)));
+        } else {
+            let len = self.line_grapheme_count(line_index);
+            let index = if after {(index + 1).min(len)} else {index};
+            self.set_cursor_position(Position::new(line_index, index));
+            self.put_text_on_cursor(&text);
+            let cursor = self.cursor_position();
+            self.set_cursor_position(Position::new(cursor.line, cursor.grapheme.saturating_sub(1)));
+        }
+    }
+
+}
@@ -0,0 +1,37 @@
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+// A place in the text as the cursor sees it, a line and a grapheme within
+// that line. The grapheme one past the last stands for the end of the line.
+// The buffer counts chars and parsers count bytes, so positions are converted
+// against the text of their line before they reach either.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub grapheme: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, grapheme: usize) -> Self {
+        Self { line, grapheme }
+    }
+
+    // Byte offset of the position in `text`, positions past the end of the
+    // line are at its end.
+    pub fn byte_in(&self, text: &str) -> usize {
+        text.grapheme_indices(true).nth(self.grapheme).map_or(text.len(), |(byte, _)| byte)
+    }
+
+    pub fn char_in(&self, text: &str) -> usize {
+        text[..self.byte_in(text)].chars().count()
+    }
+
+    // Position of the grapheme holding the char at `char_index` of `text`.
+    pub fn at_char(line: usize, text: &str, char_index: usize) -> Self {
+        let mut chars = 0;
+        let grapheme = text.graphemes(true)
+            .take_while(|g| {chars += g.chars().count(); chars <= char_index})
+            .count();
+        Self::new(line, grapheme)
+    }
+}
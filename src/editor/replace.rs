@@ -0,0 +1,243 @@
+use crossterm::event;
+use regex::{Regex, RegexBuilder};
+
+use super::Editor;
+use super::EditorMode;
+use super::buffer::TextBuffer;
+use super::history::EditKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceRange {
+    Line,
+    All,
+    Selection,
+}
+
+// A `:s` in progress, positions are (line, char) and `end` is kept in step
+// with the replacements made so far.
+#[derive(Debug)]
+pub struct Replace {
+    pattern: Regex,
+    replacement: String,
+    // `g` flag, replace every match on a line rather than just the first.
+    every_match: bool,
+    next: (usize, usize),
+    end: (usize, usize),
+    count: usize,
+    return_mode: EditorMode,
+}
+
+// A match of the pattern and the text it is to be replaced with.
+struct Found {
+    line_index: usize,
+    chars: std::ops::Range<usize>,
+    replacement: String,
+}
+
+// Splits `s/pattern/replacement/flags` into its three parts. Any punctuation
+// works as the delimiter and a backslash escapes it, `\n` and `\t` in the
+// replacement stand for a line break and a tab. The replacement is written
+// like vim's, `&` and `\0` to `\9` for the match and its groups, and comes
+// out in the `$` syntax of the regex crate.
+pub fn split_substitute(command: &str) -> Option<(String, String, String)> {
+    let rest = command.strip_prefix("substitute").or_else(|| command.strip_prefix('s'))?;
+    let delimiter = rest.chars().next().filter(|c| c.is_ascii_punctuation() && *c != '\\' && *c != '"')?;
+    let mut parts = vec![String::new()];
+    let mut chars = rest[delimiter.len_utf8()..].chars();
+    while let Some(c) = chars.next() {
+        let (in_replacement, in_flags) = (parts.len() == 2, parts.len() == 3);
+        if c == delimiter && !in_flags {
+            parts.push(String::new());
+            continue;
+        }
+        let part = parts.last_mut().unwrap();
+        let push_literal = |part: &mut String, c: char| {
+            if c == '$' && in_replacement {part.push_str("$$")} else {part.push(c)}
+        };
+        match (c, in_flags) {
+            ('\\', false) => match chars.next() {
+                Some(escaped) if escaped == delimiter => push_literal(part, escaped),
+                Some('n') if in_replacement => part.push('\n'),
+                Some('t') if in_replacement => part.push('\t'),
+                Some(escaped @ ('\\' | '&')) if in_replacement => part.push(escaped),
+                Some(digit @ '0'..='9') if in_replacement => part.push_str(&format!("${{{}}}", digit)),
+                Some(escaped) => {
+                    part.push('\\');
+                    push_literal(part, escaped);
+                }
+                None => part.push('\\'),
+            },
+            ('&', false) if in_replacement => part.push_str("${0}"),
+            (c, _) => push_literal(part, c),
+        }
+    }
+    parts.resize(3, String::new());
+    let flags = parts.pop().unwrap();
+    let replacement = parts.pop().unwrap();
+    Some((parts.pop().unwrap(), replacement, flags.trim().to_string()))
+}
+
+impl Editor {
+
+    // Ctrl+H, starts a `:s` over the selection or the whole buffer, reusing
+    // the last search pattern when there is one.
+    pub (super) fn open_replace_prompt(&mut self) {
+        let range = if self.selection_range().is_some() {""} else {"%"};
+        let pattern = if self.search.pattern.is_some() {"/"} else {""};
+        self.enter_command_mode(&format!("{}s/{}", range, pattern));
+    }
+
+    // Runs `:s` with the given flags: `g` replaces every match of a line, `c`
+    // asks before each replacement and `i`/`I` override `ignorecase`. An empty
+    // pattern stands for the last search.
+    pub (super) fn substitute(&mut self, range: ReplaceRange, pattern: &str, replacement: &str, flags: &str) -> Result<(), String> {
+        if let Some(flag) = flags.chars().find(|flag| !"gciI".contains(*flag)) {
+            return Err(format!("Unknown substitute flag: {}", flag));
+        }
+        let ignore_case = if flags.contains('I') {false} else {flags.contains('i') || self.search.ignore_case};
+        let pattern = if pattern.is_empty() {
+            self.search.pattern.clone().ok_or("No previous search pattern")?
+        } else {
+            RegexBuilder::new(pattern).case_insensitive(ignore_case).build()
+                .map_err(|_| format!("Invalid pattern: {}", pattern))?
+        };
+
+        let last_line = self.doc.buffer.len_lines() - 1;
+        let line_end = |line_index: usize| (line_index, self.doc.buffer.line(line_index).unwrap().chars().count());
+        let (start, end) = match range {
+            ReplaceRange::Line => ((self.view.cursor.line, 0), line_end(self.view.cursor.line)),
+            ReplaceRange::All => ((0, 0), line_end(last_line)),
+            ReplaceRange::Selection => self.command_line.selection.ok_or("No selection")?,
+        };
+        let replace = Replace {
+            pattern: pattern.clone(),
+            replacement: replacement.to_string(),
+            every_match: flags.contains('g'),
+            next: start,
+            end,
+            count: 0,
+            return_mode: self.mode,
+        };
+        let found = self.find_replacement(&replace).ok_or(format!("Pattern not found: {}", pattern))?;
+        self.search.pattern = Some(pattern);
+        self.doc.history.begin_group();
+        self.replace = Some(replace);
+        if flags.contains('c') {
+            self.search.highlight = true;
+            self.mode = EditorMode::Confirm;
+            self.show_replacement(&found);
+        } else {
+            self.replace_all();
+        }
+        Ok(())
+    }
+
+    // Next match at or after `replace.next` that lies entirely before
+    // `replace.end`.
+    fn find_replacement(&self, replace: &Replace) -> Option<Found> {
+        for line_index in replace.next.0..=replace.end.0 {
+            let line = self.doc.buffer.line(line_index)?;
+            let from_char = if line_index == replace.next.0 {replace.next.1} else {0};
+            if from_char > line.chars().count() {
+                continue;
+            }
+            let from = line.char_indices().nth(from_char).map_or(line.len(), |(i, _)| i);
+            let captures = match replace.pattern.captures_at(&line, from) {
+                Some(captures) => captures,
+                None => continue,
+            };
+            let found = captures.get(0).unwrap();
+            let start = line[..found.start()].chars().count();
+            let end = start + found.as_str().chars().count();
+            if line_index == replace.end.0 && end > replace.end.1 {
+                return None;
+            }
+            let mut replacement = String::new();
+            captures.expand(&replace.replacement, &mut replacement);
+            return Some(Found { line_index, chars: start..end, replacement });
+        }
+        None
+    }
+
+    // Moves past `found`, onto the next line unless every match is replaced.
+    fn skip_replacement(&mut self, found: &Found, after: (usize, usize)) {
+        let replace = self.replace.as_mut().unwrap();
+        replace.next = if !replace.every_match {
+            (after.0 + 1, 0)
+        } else if found.chars.is_empty() {
+            (after.0, after.1 + 1)
+        } else {
+            after
+        };
+    }
+
+    fn make_replacement(&mut self, found: &Found) {
+        let from = (found.line_index, found.chars.start);
+        let to = (found.line_index, found.chars.end);
+        self.replace_text(from, to, &found.replacement, EditKind::Other);
+        let after = TextBuffer::end_of(from, &found.replacement);
+        let replace = self.replace.as_mut().unwrap();
+        replace.count += 1;
+        replace.end = if replace.end.0 == found.line_index {
+            (after.0, after.1 + replace.end.1 - found.chars.end)
+        } else {
+            (replace.end.0 + after.0 - found.line_index, replace.end.1)
+        };
+        self.skip_replacement(found, after);
+    }
+
+    fn replace_all(&mut self) {
+        let mut last = None;
+        while let Some(found) = self.find_replacement(self.replace.as_ref().unwrap()) {
+            self.make_replacement(&found);
+            last = Some((found.line_index, found.chars.start));
+        }
+        if let Some(last) = last {
+            self.set_cursor_position(self.position_at_char(last).unwrap());
+        }
+        self.finish_replace();
+    }
+
+    fn show_replacement(&mut self, found: &Found) {
+        self.set_cursor_position(self.position_at_char((found.line_index, found.chars.start)).unwrap());
+    }
+
+    fn finish_replace(&mut self) {
+        let replace = match self.replace.take() {
+            Some(replace) => replace,
+            None => return,
+        };
+        self.doc.history.end_group();
+        self.mode = replace.return_mode;
+        self.header_msg = format!("{} replacement{}", replace.count, if replace.count == 1 {""} else {"s"});
+        self.clamp_cursor_to_line();
+    }
+
+    pub (super) fn replace_prompt(&self) -> String {
+        match &self.replace {
+            Some(replace) => format!("replace with {} (y/n/a/q)?", replace.replacement.replace('\n', "\\n")),
+            None => String::new(),
+        }
+    }
+
+    // y replaces the shown match, n skips it, a replaces it and all after
+    // it, q and Esc stop.
+    pub (super) fn process_confirm_key(&mut self, key_event: event::KeyEvent) {
+        let found = match self.replace.as_ref().and_then(|replace| self.find_replacement(replace)) {
+            Some(found) => found,
+            None => return self.finish_replace(),
+        };
+        match key_event.code {
+            event::KeyCode::Char('y') => self.make_replacement(&found),
+            event::KeyCode::Char('n') => self.skip_replacement(&found, (found.line_index, found.chars.end)),
+            event::KeyCode::Char('a') => return self.replace_all(),
+            event::KeyCode::Char('q') | event::KeyCode::Esc => return self.finish_replace(),
+            _ => return,
+        }
+        match self.replace.as_ref().and_then(|replace| self.find_replacement(replace)) {
+            Some(found) => self.show_replacement(&found),
+            None => self.finish_replace(),
+        }
+    }
+
+}
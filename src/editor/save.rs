@@ -0,0 +1,137 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::history;
+
+// Writes `content` to a temporary file next to `path` and renames it over
+// the original, so a crash or a full disk leaves either the old or the new
+// version and never a truncated one.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    // Saving through a symlink replaces the file it points to.
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let metadata = fs::metadata(&target).ok();
+    replace(&target, content, |temp| create_like(temp, metadata.as_ref()))
+}
+
+// Like `write_atomically`, the new file is only readable by its owner.
+pub fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    replace(path, content, |temp| {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(temp)
+    })
+}
+
+// Creates `dir` and its missing parents, and keeps `dir` closed to others.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+    // Folders made before are closed too.
+    #[cfg(unix)]
+    fs::set_permissions(dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+    Ok(())
+}
+
+// Renames a temporary file made by `create` and filled with `content` over
+// `target`.
+fn replace(target: &Path, content: &[u8], create: impl FnOnce(&Path) -> io::Result<File>) -> io::Result<()> {
+    let name = target.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?
+        .to_string_lossy();
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = dir.join(format!(".{}.{}.kite-tmp", name, std::process::id()));
+    // Not writing in place when the folder is closed keeps the promise above,
+    // a file in such a folder is opened read-only to begin with.
+    let mut file = create(&temp).map_err(|e| {
+        io::Error::new(e.kind(), format!("could not create a temporary file in {}: {}", dir.display(), e))
+    })?;
+    let written = file.write_all(content)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp, target));
+    if written.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    written?;
+    // The rename itself only lasts once the folder is on disk too.
+    if let Ok(dir) = File::open(dir) {
+        dir.sync_all().ok();
+    }
+    Ok(())
+}
+
+// Creates `path` with the owner and permissions of the file it replaces
+// before anything is written to it, so the text is never more exposed than
+// it was.
+fn create_like(path: &Path, metadata: Option<&fs::Metadata>) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(metadata) = metadata {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(metadata.permissions().mode() & 0o777);
+    }
+    let file = options.open(path)?;
+    if let Some(metadata) = metadata {
+        // Only root can give files away, others keep their own ownership.
+        // Changing the owner clears setuid and setgid, so it comes first.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            std::os::unix::fs::chown(path, Some(metadata.uid()), Some(metadata.gid())).ok();
+        }
+        if let Err(e) = fs::set_permissions(path, metadata.permissions()) {
+            fs::remove_file(path).ok();
+            return Err(e);
+        }
+    }
+    Ok(file)
+}
+
+// Saving replaces the file through its folder, so both have to be writable.
+pub fn can_write(path: &Path) -> bool {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    writable(&target) && writable(dir)
+}
+
+#[cfg(unix)]
+fn writable(path: &Path) -> bool {
+    rustix::fs::access(path, rustix::fs::Access::WRITE_OK).is_ok()
+}
+
+#[cfg(not(unix))]
+fn writable(path: &Path) -> bool {
+    fs::metadata(path).map_or(false, |metadata| !metadata.permissions().readonly())
+}
+
+// Copies the version of `path` on disk before it is overwritten, to `path~`
+// or into `dir` named after its whole path like the undo cache.
+pub fn back_up(path: &Path, dir: &str) -> io::Result<()> {
+    let target = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let backup = if dir.is_empty() {
+        let mut backup = target.clone().into_os_string();
+        backup.push("~");
+        PathBuf::from(backup)
+    } else {
+        fs::create_dir_all(dir)?;
+        Path::new(dir).join(format!("{}~", history::path_key(&target)))
+    };
+    fs::copy(&target, backup)?;
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::io::Write;
+
+use crossterm::{*, style::{Color, Print}};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Shown for graphemes that have no width of their own or would move the
+// terminal's cursor, e.g. controls and lone combining marks.
+const PLACEHOLDER: &str = "\u{FFFD}";
+
+fn is_unprintable(grapheme: &str) -> bool {
+    grapheme.width() == 0 || grapheme.chars().any(char::is_control)
+}
+
+// Cells `grapheme` takes up on screen, two for wide ones such as CJK and
+// most emoji.
+pub fn display_width(grapheme: &str) -> usize {
+    if is_unprintable(grapheme) {1} else {grapheme.width()}
+}
+
+// Colors of a cell, None leaves the terminal's default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CellStyle {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+}
+
+impl CellStyle {
+    pub fn new(foreground: Color, background: Color) -> Self {
+        Self { foreground: Some(foreground), background: Some(background) }
+    }
+}
+
+// The cells covered by the right part of a wide grapheme have an empty
+// symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cell {
+    symbol: String,
+    style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { symbol: " ".to_string(), style: CellStyle::default() }
+    }
+}
+
+// Grid of the cells making up a frame. Drawing fills in the next frame and
+// `flush` only sends the cells that differ from the previous one.
+#[derive(Debug, Default)]
+pub struct Screen {
+    size: (u16, u16),
+    cells: Vec<Cell>,
+    previous: Vec<Cell>,
+    // The terminal content is unknown, e.g. after a resize.
+    invalid: bool,
+}
+
+impl Screen {
+    // Starts a blank frame of `size`.
+    pub fn begin_frame(&mut self, size: (u16, u16)) {
+        if size != self.size || self.previous.len() != self.cells.len() {
+            self.size = size;
+            self.previous = vec![Cell::default(); size.0 as usize * size.1 as usize];
+            self.invalid = true;
+        }
+        self.cells.clear();
+        self.cells.resize(size.0 as usize * size.1 as usize, Cell::default());
+    }
+
+    // Forces the next flush to send every cell.
+    pub fn invalidate(&mut self) {
+        self.invalid = true;
+    }
+
+    // Writes `text` from `at`, cut off at the right edge of the screen.
+    // Returns the number of cells written.
+    pub fn put(&mut self, at: (u16, u16), text: &str, style: CellStyle) -> u16 {
+        if at.1 >= self.size.1 {
+            return 0;
+        }
+        let mut x = at.0;
+        for grapheme in text.graphemes(true) {
+            let width = display_width(grapheme) as u16;
+            if x + width > self.size.0 {
+                // Half a wide grapheme is left blank.
+                while x < self.size.0 {
+                    self.set((x, at.1), " ", style);
+                    x += 1;
+                }
+                break;
+            }
+            self.set((x, at.1), if is_unprintable(grapheme) {PLACEHOLDER} else {grapheme}, style);
+            for offset in 1..width {
+                self.set((x + offset, at.1), "", style);
+            }
+            x += width;
+        }
+        x - at.0
+    }
+
+    // Overwriting either half of a wide grapheme blanks the other one.
+    fn set(&mut self, at: (u16, u16), symbol: &str, style: CellStyle) {
+        let index = at.1 as usize * self.size.0 as usize + at.0 as usize;
+        if self.cells[index].symbol.is_empty() && at.0 > 0 && !symbol.is_empty() {
+            self.cells[index - 1].symbol = " ".to_string();
+        }
+        if display_width(&self.cells[index].symbol) > 1 && at.0 + 1 < self.size.0 {
+            self.cells[index + 1].symbol = " ".to_string();
+        }
+        self.cells[index] = Cell { symbol: symbol.to_string(), style };
+    }
+
+    pub fn flush<W>(&mut self, out: &mut W) -> std::io::Result<()>
+        where W: Write
+    {
+        let mut cursor_at = None;
+        let mut current_style = None;
+        for y in 0..self.size.1 {
+            for x in 0..self.size.0 {
+                let index = y as usize * self.size.0 as usize + x as usize;
+                let cell = &self.cells[index];
+                // Printed along with the wide grapheme before it.
+                if cell.symbol.is_empty() {
+                    continue;
+                }
+                let width = display_width(&cell.symbol).min((self.size.0 - x) as usize);
+                if !self.invalid && self.cells[index..index + width] == self.previous[index..index + width] {
+                    continue;
+                }
+                if cursor_at != Some((x, y)) {
+                    out.queue(cursor::MoveTo(x, y))?;
+                }
+                if current_style != Some(cell.style) {
+                    out.queue(style::SetForegroundColor(cell.style.foreground.unwrap_or(Color::Reset)))?;
+                    out.queue(style::SetBackgroundColor(cell.style.background.unwrap_or(Color::Reset)))?;
+                    current_style = Some(cell.style);
+                }
+                out.queue(Print(&cell.symbol))?;
+                cursor_at = Some((x + width as u16, y));
+            }
+        }
+        if current_style.is_some() {
+            out.queue(style::ResetColor)?;
+        }
+        std::mem::swap(&mut self.cells, &mut self.previous);
+        self.invalid = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crossterm::event;
+use regex::{Regex, RegexBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::Editor;
+use super::EditorMode;
+use super::command::CommandLine;
+use super::position::Position;
+
+#[derive(Debug, Default)]
+pub struct Search {
+    pub prompt: CommandLine,
+    pub backward: bool,
+    // Treat the pattern as a regular expression instead of literal text.
+    pub use_regex: bool,
+    pub ignore_case: bool,
+    // Last accepted (or, while typing, the current) pattern.
+    pub pattern: Option<Regex>,
+    pub highlight: bool,
+    // Pattern accepted before the prompt opened, back in use if it is left.
+    previous: Option<Regex>,
+    origin: Position,
+    origin_display: (usize, usize),
+    return_mode: Option<EditorMode>,
+}
+
+impl Search {
+    fn compile(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let pattern = if self.use_regex {pattern.to_string()} else {regex::escape(pattern)};
+        RegexBuilder::new(&pattern).case_insensitive(self.ignore_case).build()
+    }
+}
+
+// Grapheme ranges of every match of `pattern` in `line`.
+pub fn line_matches(pattern: &Regex, line: &str) -> Vec<std::ops::Range<usize>> {
+    let mut graphemes = line.grapheme_indices(true).map(|(i, _)| i).peekable();
+    let mut grapheme = 0;
+    let mut to_grapheme = |byte: usize| {
+        while graphemes.next_if(|i| *i < byte).is_some() {
+            grapheme += 1;
+        }
+        grapheme
+    };
+    pattern.find_iter(line)
+        .filter(|m| !m.is_empty())
+        .map(|m| {
+            let start = to_grapheme(m.start());
+            start..to_grapheme(m.end())
+        })
+        .collect()
+}
+
+impl Editor {
+
+    pub (super) fn enter_search_mode(&mut self, backward: bool) {
+        self.search.return_mode = Some(self.mode);
+        self.search.backward = backward;
+        self.search.previous = self.search.pattern.clone();
+        self.search.origin = self.cursor_position();
+        self.search.origin_display = (self.view.display_top_line_index, self.view.display_left_column);
+        self.search.prompt.reset();
+        self.mode = EditorMode::Search;
+    }
+
+    fn leave_search_mode(&mut self) {
+        self.mode = self.search.return_mode.take().unwrap_or(EditorMode::Normal);
+    }
+
+    pub (super) fn process_search_key(&mut self, key_event: event::KeyEvent) {
+        let alt = key_event.modifiers.contains(event::KeyModifiers::ALT);
+        match key_event.code {
+            event::KeyCode::Esc => {
+                (self.view.display_top_line_index, self.view.display_left_column) = self.search.origin_display;
+                self.set_cursor_position(self.search.origin);
+                self.search.pattern = self.search.previous.take();
+                self.leave_search_mode();
+                return;
+            }
+            event::KeyCode::Enter => {
+                self.search.prompt.submit();
+                self.leave_search_mode();
+                return;
+            }
+            event::KeyCode::Backspace if self.search.prompt.input.is_empty() => {
+                self.search.pattern = self.search.previous.take();
+                self.leave_search_mode();
+                return;
+            }
+            event::KeyCode::Char('r') if alt => {
+                self.search.use_regex = !self.search.use_regex;
+            }
+            event::KeyCode::Char('c') if alt => {
+                self.search.ignore_case = !self.search.ignore_case;
+            }
+            code => {
+                self.search.prompt.edit(code);
+            }
+        }
+        self.update_incremental_search();
+    }
+
+    // Jumps to the first match of the pattern typed so far, counted from where
+    // the search started.
+    pub (super) fn update_incremental_search(&mut self) {
+        self.search.pattern = None;
+        self.search.highlight = true;
+        if self.search.prompt.input.is_empty() {
+            self.set_cursor_position(self.search.origin);
+            return;
+        }
+        match self.search.compile(&self.search.prompt.input) {
+            Ok(pattern) => self.search.pattern = Some(pattern),
+            Err(_) => {
+                self.header_msg = "Invalid pattern".to_string();
+                return;
+            }
+        }
+        match self.find_match(self.search.origin, self.search.backward) {
+            Some((position, wrapped)) => {
+                self.header_msg = if wrapped {"Search wrapped around".to_string()} else {String::new()};
+                self.set_cursor_position(position);
+            }
+            None => {
+                self.header_msg = format!("Pattern not found: {}", self.search.prompt.input);
+                self.set_cursor_position(self.search.origin);
+            }
+        }
+    }
+
+    // Start of the nearest match strictly after (or before) `from`, and
+    // whether the search had to wrap around the end of the buffer for it.
+    fn find_match(&self, from: Position, backward: bool) -> Option<(Position, bool)> {
+        let pattern = self.search.pattern.as_ref()?;
+        let line_count = self.doc.buffer.len_lines();
+        for step in 0..=line_count {
+            let line_index = if backward {
+                (from.line + line_count * 2 - step) % line_count
+            } else {
+                (from.line + step) % line_count
+            };
+            let matches = line_matches(pattern, &self.doc.buffer.line(line_index).unwrap());
+            let found = if step == 0 {
+                if backward {
+                    matches.iter().rev().find(|m| m.start < from.grapheme)
+                } else {
+                    matches.iter().find(|m| m.start > from.grapheme)
+                }
+            } else if backward {
+                matches.last()
+            } else {
+                matches.first()
+            };
+            if let Some(found) = found {
+                let wrapped = if backward {line_index > from.line || step == line_count} else {line_index < from.line || step == line_count};
+                return Some((Position::new(line_index, found.start), wrapped));
+            }
+        }
+        None
+    }
+
+    // `n` and `N`, repeats the last search in its (or the opposite) direction.
+    pub (super) fn search_next(&mut self, reverse: bool) {
+        if self.search.pattern.is_none() {
+            self.header_msg = "No previous search pattern".to_string();
+            return;
+        }
+        self.search.highlight = true;
+        let backward = self.search.backward != reverse;
+        match self.find_match(self.cursor_position(), backward) {
+            Some((position, wrapped)) => {
+                self.header_msg = if wrapped {"Search wrapped around".to_string()} else {String::new()};
+                self.set_cursor_position(position);
+            }
+            None => self.header_msg = "Pattern not found".to_string(),
+        }
+    }
+
+}
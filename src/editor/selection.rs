@@ -0,0 +1,87 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::Editor;
+use super::EditorMode;
+use super::history::EditKind;
+use super::position::Position;
+
+impl Editor {
+
+    // Call before moving the cursor, starts (or keeps) a selection anchored at
+    // the current cursor if `extend`, otherwise drops any selection.
+    pub (super) fn begin_motion(&mut self, extend: bool) {
+        if !extend {
+            self.view.selection_anchor = None;
+        } else if self.view.selection_anchor.is_none() {
+            self.view.selection_anchor = Some(self.cursor_position());
+        }
+    }
+
+    pub (super) fn clear_selection(&mut self) {
+        self.view.selection_anchor = None;
+    }
+
+    // Ordered bounds of the selection, end exclusive. In visual mode the
+    // grapheme under the cursor is part of the selection.
+    pub (super) fn selection_range(&self) -> Option<(Position, Position)> {
+        let anchor = self.view.selection_anchor?;
+        let cursor = self.cursor_position();
+        let (start, end) = if anchor < cursor {(anchor, cursor)} else {(cursor, anchor)};
+        let end = if self.mode == EditorMode::Visual {self.position_after(end)} else {end};
+        if start == end {None} else {Some((start, end))}
+    }
+
+    // Selected graphemes of the given line, the newline counts as one past the
+    // last grapheme.
+    pub (super) fn selection_on_line(&self, line_index: usize) -> Option<std::ops::Range<usize>> {
+        let (start, end) = self.selection_range()?;
+        if line_index < start.line || line_index > end.line {
+            return None;
+        }
+        let from = if line_index == start.line {start.grapheme} else {0};
+        let to = if line_index == end.line {
+            end.grapheme
+        } else {
+            self.line_at_index(line_index)?.graphemes(true).count() + 1
+        };
+        Some(from..to)
+    }
+
+    pub (super) fn selection_char_range(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (start, end) = self.selection_range()?;
+        Some((self.char_position(start)?, self.char_position(end)?))
+    }
+
+    pub (super) fn selected_text(&self) -> Option<String> {
+        let (from, to) = self.selection_char_range()?;
+        Some(self.doc.buffer.text_between(from, to))
+    }
+
+    // Removes the selected text and leaves the cursor where it started,
+    // returns false if nothing was selected.
+    pub (super) fn delete_selection(&mut self) -> bool {
+        self.remove_selection(EditKind::Other)
+    }
+
+    // Removes the selection as the start of an insertion, so that the text
+    // typed in its place undoes along with it.
+    pub (super) fn type_over_selection(&mut self) {
+        self.remove_selection(EditKind::Insert);
+    }
+
+    fn remove_selection(&mut self, kind: EditKind) -> bool {
+        let (start, _) = match self.selection_range() {
+            Some(range) => range,
+            None => {
+                self.clear_selection();
+                return false;
+            }
+        };
+        let (from, to) = self.selection_char_range().unwrap();
+        self.remove_text(from, to, kind);
+        self.clear_selection();
+        self.set_cursor_position(start);
+        true
+    }
+
+}
@@ -0,0 +1,736 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use proptest::prelude::*;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::Editor;
+use super::EditorMode;
+use super::buffer::TextBuffer;
+use super::document::Document;
+use super::encoding::FileEncoding;
+use super::history::{EditKind, History};
+use super::position::Position;
+
+// Pieces of text that trip up byte or char indexing: combining marks,
+// wide characters, emoji sequences, scripts with clusters and tabs.
+const PIECES: &[&str] = &[
+    "a", "Z", " ", "_", ".", "é", "e\u{301}", "\u{301}", "漢", "字", "🙂",
+    "👩\u{200D}👩\u{200D}👧", "\u{1F1EF}\u{1F1F5}", "ก\u{E31}", "ᄀ\u{1161}\u{11A8}", "\u{200B}", "\t",
+];
+
+fn text() -> impl Strategy<Value = String> {
+    prop::collection::vec(prop::sample::select([PIECES, &["\n"; 3]].concat()), 0..40)
+        .prop_map(|pieces| pieces.concat())
+}
+
+fn line() -> impl Strategy<Value = String> {
+    prop::collection::vec(prop::sample::select(PIECES), 0..20).prop_map(|pieces| pieces.concat())
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Type(char),
+    Paste(String),
+    Enter,
+    Backspace,
+    // Tab or Shift+Tab.
+    Indent(KeyCode),
+    Move(KeyCode),
+    Select(KeyCode),
+    Undo,
+    Redo,
+    // Keys pressed in normal mode before going back to insert mode.
+    Normal(&'static str),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => prop::sample::select(vec!['a', ' ', 'é', '\u{301}', '漢', '🙂', '\u{200D}', 'ก', '\u{E31}']).prop_map(Op::Type),
+        1 => text().prop_map(Op::Paste),
+        1 => Just(Op::Enter),
+        2 => Just(Op::Backspace),
+        1 => prop::sample::select(vec![KeyCode::Tab, KeyCode::BackTab]).prop_map(Op::Indent),
+        4 => prop::sample::select(vec![KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down, KeyCode::Home, KeyCode::End])
+            .prop_map(Op::Move),
+        1 => prop::sample::select(vec![KeyCode::Left, KeyCode::Right, KeyCode::Down]).prop_map(Op::Select),
+        1 => Just(Op::Undo),
+        1 => Just(Op::Redo),
+        2 => prop::sample::select(vec!["x", "X", "dw", "de", "db", "cwz", "diw", "daw", "D", "dd", "yyp", "p", "P", "w", "e", "b", "$", "u", "o"])
+            .prop_map(Op::Normal),
+    ]
+}
+
+fn editor_with(text: &str) -> Editor {
+    let mut editor = Editor::default();
+    editor.doc.buffer = TextBuffer::from_lines(text.split('\n'));
+    editor.set_text_size((12, 4));
+    editor
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+// Columns taken up by the blanks `line` starts with, a blank with a mark
+// on it is not one.
+fn indent_columns(line: &str, tab_width: usize) -> usize {
+    line.graphemes(true).take_while(|g| *g == " " || *g == "\t").fold(0, |column, g| {
+        if g == "\t" {column / tab_width * tab_width + tab_width} else {column + 1}
+    })
+}
+
+fn buffer_text(editor: &Editor) -> String {
+    editor.doc.buffer.lines().collect::<Vec<_>>().join("\n")
+}
+
+fn press(editor: &mut Editor, code: KeyCode, modifiers: KeyModifiers) {
+    editor.process_event(Event::Key(KeyEvent::new(code, modifiers))).unwrap();
+}
+
+fn apply(editor: &mut Editor, op: &Op) {
+    match op {
+        Op::Type(c) => press(editor, KeyCode::Char(*c), KeyModifiers::NONE),
+        Op::Paste(text) => {editor.process_event(Event::Paste(text.clone())).unwrap();}
+        Op::Enter => press(editor, KeyCode::Enter, KeyModifiers::NONE),
+        Op::Backspace => press(editor, KeyCode::Backspace, KeyModifiers::NONE),
+        Op::Indent(code) => press(editor, *code, KeyModifiers::NONE),
+        Op::Move(code) => press(editor, *code, KeyModifiers::NONE),
+        Op::Select(code) => press(editor, *code, KeyModifiers::SHIFT),
+        Op::Undo => press(editor, KeyCode::Char('z'), KeyModifiers::CONTROL),
+        Op::Redo => press(editor, KeyCode::Char('y'), KeyModifiers::CONTROL),
+        Op::Normal(keys) => {
+            press(editor, KeyCode::Esc, KeyModifiers::NONE);
+            for c in keys.chars() {
+                press(editor, KeyCode::Char(c), KeyModifiers::NONE);
+            }
+            if editor.mode != EditorMode::Insert {
+                press(editor, KeyCode::Char('i'), KeyModifiers::NONE);
+            }
+        }
+    }
+}
+
+fn check_positions(editor: &Editor) -> Result<(), TestCaseError> {
+    let cursor = editor.cursor_position();
+    prop_assert!(cursor.line < editor.doc.buffer.len_lines());
+    prop_assert!(cursor.grapheme <= editor.line_grapheme_count(cursor.line));
+    let at = editor.char_position(cursor).unwrap();
+    prop_assert_eq!(editor.position_at_char(at), Some(cursor));
+    if let Some((start, end)) = editor.selection_range() {
+        prop_assert!(start < end);
+        prop_assert!(editor.char_position(start).is_some() && editor.char_position(end).is_some());
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn conversions_round_trip(line in line()) {
+        let count = line.graphemes(true).count();
+        let mut previous = None;
+        for grapheme in 0..=count {
+            let pos = Position::new(0, grapheme);
+            let byte = pos.byte_in(&line);
+            prop_assert!(line.is_char_boundary(byte));
+            prop_assert_eq!(line[..byte].graphemes(true).count(), grapheme);
+            let char_index = pos.char_in(&line);
+            prop_assert_eq!(line[..byte].chars().count(), char_index);
+            prop_assert_eq!(Position::at_char(0, &line, char_index), pos);
+            prop_assert!(previous < Some(char_index) || grapheme == 0);
+            previous = Some(char_index);
+        }
+        prop_assert_eq!(Position::new(0, count + 3).byte_in(&line), line.len());
+    }
+
+    #[test]
+    fn moving_right_visits_every_grapheme(text in text()) {
+        let mut editor = editor_with(&text);
+        let lines: Vec<String> = editor.doc.buffer.lines().map(|line| line.into_owned()).collect();
+        let steps: usize = lines.iter().map(|line| line.graphemes(true).count() + 1).sum::<usize>() - 1;
+        for _ in 0..steps {
+            let before = editor.cursor_position();
+            editor.move_cursor_right();
+            prop_assert!(before < editor.cursor_position());
+            check_positions(&editor)?;
+        }
+        let last = lines.len() - 1;
+        prop_assert_eq!(editor.cursor_position(), Position::new(last, lines[last].graphemes(true).count()));
+        for _ in 0..steps {
+            editor.move_cursor_left();
+        }
+        prop_assert_eq!(editor.cursor_position(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn typing_inserts_at_the_cursor(text in text(), ops in prop::collection::vec(op(), 0..60)) {
+        let mut editor = editor_with(&text);
+        let mut expected = text.clone();
+        for op in ops.iter().filter(|op| matches!(op, Op::Type(_) | Op::Enter | Op::Move(_))) {
+            let (line_index, char_index) = editor.cursor_char_position();
+            let offset: usize = expected.split('\n').take(line_index).map(|line| line.chars().count() + 1).sum::<usize>() + char_index;
+            let byte = expected.char_indices().nth(offset).map_or(expected.len(), |(byte, _)| byte);
+            match op {
+                Op::Type(c) => expected.insert(byte, *c),
+                Op::Enter => {
+                    let start = expected[..byte].rfind('\n').map_or(0, |i| i + 1);
+                    let end = expected[byte..].find('\n').map_or(expected.len(), |i| byte + i);
+                    let (before, after) = (&expected[start..byte], &expected[byte..end]);
+                    // Plain text keeps the indentation, unless the cursor is still in it.
+                    let inserted = if before.trim_start_matches(is_blank).is_empty() {
+                        (0, "\n".to_string())
+                    } else {
+                        let blanks = after.len() - after.trim_start_matches(is_blank).len();
+                        (blanks, format!("\n{}", " ".repeat(indent_columns(before, editor.tab_width as usize))))
+                    };
+                    expected.replace_range(byte..byte + inserted.0, &inserted.1);
+                }
+                _ => {}
+            }
+            apply(&mut editor, op);
+            prop_assert_eq!(buffer_text(&editor), expected.clone());
+            check_positions(&editor)?;
+        }
+    }
+
+    #[test]
+    fn edits_keep_positions_valid_and_undo(text in text(), ops in prop::collection::vec(op(), 0..60), expand_tab: bool) {
+        let mut editor = editor_with(&text);
+        editor.expand_tab = expand_tab;
+        for op in ops.iter() {
+            apply(&mut editor, op);
+            check_positions(&editor)?;
+        }
+        let edited = buffer_text(&editor);
+        let mut undone = 0;
+        while editor.undo() {
+            check_positions(&editor)?;
+            undone += 1;
+        }
+        prop_assert_eq!(buffer_text(&editor), text);
+        for _ in 0..undone {
+            prop_assert!(editor.redo());
+            check_positions(&editor)?;
+        }
+        prop_assert_eq!(buffer_text(&editor), edited);
+    }
+}
+
+fn normal_keys(editor: &mut Editor, keys: &str) {
+    press(editor, KeyCode::Esc, KeyModifiers::NONE);
+    for c in keys.chars() {
+        press(editor, KeyCode::Char(c), KeyModifiers::NONE);
+    }
+}
+
+fn default_register(editor: &Editor) -> Option<String> {
+    editor.clipboard.get(super::clipboard::DEFAULT_REGISTER).map(|register| register.text.clone())
+}
+
+#[test]
+fn deleting_to_the_end_of_an_empty_line_keeps_its_line_break() {
+    for keys in ["D", "d$", "x", "X"] {
+        let mut editor = editor_with("ab\n\ncd");
+        normal_keys(&mut editor, "x");
+        editor.set_cursor_position(Position::new(1, 0));
+        normal_keys(&mut editor, keys);
+        assert_eq!(buffer_text(&editor), "b\n\ncd", "after {}", keys);
+        assert_eq!(default_register(&editor).as_deref(), Some("a"), "after {}", keys);
+        assert_eq!(editor.cursor_position(), Position::new(1, 0));
+    }
+}
+
+#[test]
+fn a_line_count_past_the_end_goes_to_the_last_line() {
+    let mut editor = editor_with("ab\ncd\nef");
+    normal_keys(&mut editor, "100ggx");
+    assert_eq!(buffer_text(&editor), "ab\ncd\nf");
+    let mut editor = editor_with("ab\ncd\nef");
+    normal_keys(&mut editor, "");
+    editor.set_cursor_position(Position::new(1, 0));
+    normal_keys(&mut editor, "d100gg");
+    assert_eq!(buffer_text(&editor), "ab");
+}
+
+#[test]
+fn changing_to_the_end_of_an_empty_line_types_into_it() {
+    let mut editor = editor_with("ab\n\ncd");
+    editor.set_cursor_position(Position::new(1, 0));
+    normal_keys(&mut editor, "C");
+    assert!(editor.mode == EditorMode::Insert);
+    press(&mut editor, KeyCode::Char('z'), KeyModifiers::NONE);
+    assert_eq!(buffer_text(&editor), "ab\nz\ncd");
+}
+
+#[test]
+fn deleting_to_the_end_of_a_line_stops_before_its_line_break() {
+    let mut editor = editor_with("abc\n\nd");
+    normal_keys(&mut editor, "");
+    editor.set_cursor_position(Position::new(0, 1));
+    normal_keys(&mut editor, "D");
+    assert_eq!(buffer_text(&editor), "a\n\nd");
+    assert_eq!(default_register(&editor).as_deref(), Some("bc"));
+    let mut editor = editor_with("abc\n\nd");
+    normal_keys(&mut editor, "");
+    editor.set_cursor_position(Position::new(0, 1));
+    normal_keys(&mut editor, "2D");
+    assert_eq!(buffer_text(&editor), "a\nd");
+}
+
+fn editor_for(path: &str, text: &str, cursor: Position) -> Editor {
+    let mut editor = editor_with(text);
+    editor.doc.path = path.to_string();
+    editor.set_cursor_position(cursor);
+    editor
+}
+
+fn type_text(editor: &mut Editor, text: &str) {
+    for c in text.chars() {
+        let code = if c == '\n' {KeyCode::Enter} else {KeyCode::Char(c)};
+        press(editor, code, KeyModifiers::NONE);
+    }
+}
+
+#[test]
+fn enter_after_an_opener_indents_a_level_deeper() {
+    let mut editor = editor_for("a.rs", "fn f() {", Position::new(0, 8));
+    type_text(&mut editor, "\nx");
+    assert_eq!(buffer_text(&editor), "fn f() {\n    x");
+    let mut editor = editor_for("a.py", "  if x:", Position::new(0, 7));
+    type_text(&mut editor, "\nx");
+    assert_eq!(buffer_text(&editor), "  if x:\n      x");
+    // Openers that are words only count as whole words.
+    let mut editor = editor_for("a.sh", "echo redo", Position::new(0, 9));
+    type_text(&mut editor, "\nx");
+    assert_eq!(buffer_text(&editor), "echo redo\nx");
+}
+
+#[test]
+fn enter_between_an_opener_and_its_closer_puts_the_closer_on_its_own_line() {
+    let mut editor = editor_for("a.rs", "    f() {}", Position::new(0, 9));
+    type_text(&mut editor, "\nx");
+    assert_eq!(buffer_text(&editor), "    f() {\n        x\n    }");
+    assert_eq!(editor.cursor_position(), Position::new(1, 9));
+}
+
+#[test]
+fn typing_a_closer_alone_dedents_it() {
+    let mut editor = editor_for("a.rs", "fn f() {\n    x;", Position::new(1, 6));
+    type_text(&mut editor, "\n}");
+    assert_eq!(buffer_text(&editor), "fn f() {\n    x;\n}");
+    let mut editor = editor_for("a.sh", "if true; then\n    echo", Position::new(1, 8));
+    type_text(&mut editor, "\nfi\n");
+    assert_eq!(buffer_text(&editor), "if true; then\n    echo\nfi\n");
+    let mut editor = editor_for("a.sh", "for x in y; do\n    echo", Position::new(1, 8));
+    type_text(&mut editor, "\ndone;");
+    assert_eq!(buffer_text(&editor), "for x in y; do\n    echo\ndone;");
+    // Not when something else is on the line.
+    let mut editor = editor_for("a.rs", "fn f() {\n    x;", Position::new(1, 6));
+    type_text(&mut editor, "\ny}");
+    assert_eq!(buffer_text(&editor), "fn f() {\n    x;\n    y}");
+}
+
+#[test]
+fn a_word_starting_with_a_closer_keeps_its_indentation() {
+    for word in ["find .", "done_count=1", "elsewhere"] {
+        let mut editor = editor_for("a.sh", "if true; then\n    echo", Position::new(1, 8));
+        type_text(&mut editor, &format!("\n{}\n", word));
+        assert_eq!(buffer_text(&editor), format!("if true; then\n    echo\n    {}\n    ", word));
+    }
+}
+
+#[test]
+fn opening_a_line_above_copies_the_indentation() {
+    let mut editor = editor_for("a.txt", "\tab\n", Position::new(0, 0));
+    normal_keys(&mut editor, "O");
+    assert!(editor.mode == EditorMode::Insert);
+    type_text(&mut editor, "x");
+    assert_eq!(buffer_text(&editor), "    x\n\tab\n");
+}
+
+#[test]
+fn typing_over_a_selection_undoes_in_one_step() {
+    for typed in ["xy", "\tx", "\nx"] {
+        let mut editor = editor_with("ab cd\nef");
+        editor.set_cursor_position(Position::new(0, 1));
+        press(&mut editor, KeyCode::Right, KeyModifiers::SHIFT);
+        press(&mut editor, KeyCode::Right, KeyModifiers::SHIFT);
+        type_text(&mut editor, typed);
+        assert!(editor.undo());
+        // A new line starts a step of its own.
+        if typed.starts_with('\n') {
+            assert_eq!(buffer_text(&editor), "a\ncd\nef");
+            assert!(editor.undo());
+        }
+        assert_eq!(buffer_text(&editor), "ab cd\nef", "after {:?}", typed);
+    }
+}
+
+#[test]
+fn changing_and_typing_undoes_in_one_step() {
+    for keys in ["cw", "cc", "C", "s"] {
+        let mut editor = editor_with("ab cd\nef");
+        normal_keys(&mut editor, keys);
+        type_text(&mut editor, "xyz");
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        normal_keys(&mut editor, "u");
+        assert_eq!(buffer_text(&editor), "ab cd\nef", "after {}", keys);
+    }
+}
+
+#[test]
+fn putting_over_a_visual_selection_undoes_in_one_step() {
+    let mut editor = editor_with("ab cd\nef");
+    normal_keys(&mut editor, "yiwwviwp");
+    assert_eq!(buffer_text(&editor), "ab ab\nef");
+    normal_keys(&mut editor, "u");
+    assert_eq!(buffer_text(&editor), "ab cd\nef");
+}
+
+fn round_trip(bytes: &[u8], name: &str, bom: bool, text: &str) {
+    let (encoding, decoded, valid) = FileEncoding::decode(bytes, None);
+    assert!(valid);
+    assert_eq!((encoding.name(), encoding.bom, decoded.as_str()), (name, bom, text));
+    assert!(!encoding.is_binary(bytes));
+    assert_eq!(encoding.encode(&decoded).as_deref(), Some(bytes));
+}
+
+#[test]
+fn utf8_round_trips_with_and_without_a_bom() {
+    round_trip("caf\u{e9} 漢\n".as_bytes(), "UTF-8", false, "caf\u{e9} 漢\n");
+    round_trip(b"\xEF\xBB\xBFcaf\xC3\xA9\n", "UTF-8", true, "caf\u{e9}\n");
+}
+
+#[test]
+fn utf16_round_trips_with_and_without_a_bom() {
+    round_trip(b"\xFF\xFEh\0i\0\n\0", "UTF-16LE", true, "hi\n");
+    round_trip(b"\xFE\xFF\0h\0i\0\n", "UTF-16BE", true, "hi\n");
+    round_trip(b"h\0i\0 \0\x34\x6C\n\0", "UTF-16LE", false, "hi \u{6C34}\n");
+    round_trip(b"\0h\0i\0 \x6C\x34\0\n", "UTF-16BE", false, "hi \u{6C34}\n");
+}
+
+#[test]
+fn legacy_encodings_round_trip() {
+    round_trip(b"caf\xE9 cr\xE8me br\xFBl\xE9e \x80 d\xE9j\xE0 vu\n", "windows-1252", false, "caf\u{e9} cr\u{e8}me br\u{fb}l\u{e9}e \u{20ac} d\u{e9}j\u{e0} vu\n");
+    let (encoding, text, _) = FileEncoding::decode(b"na\xEFve\n", Some(encoding_rs::WINDOWS_1252));
+    assert_eq!((encoding.name(), text.as_str()), ("windows-1252", "na\u{ef}ve\n"));
+}
+
+#[test]
+fn text_with_zero_bytes_is_not_taken_for_utf16() {
+    // Zeros in both halves, or an odd length, are not UTF-16.
+    for bytes in [&b"a\0\0b\0c"[..], b"a\0b\0c", b"\0\0\0\0"] {
+        let (encoding, _, _) = FileEncoding::decode(bytes, None);
+        assert_eq!(encoding.name(), "UTF-8");
+        assert!(encoding.is_binary(bytes));
+    }
+}
+
+#[test]
+fn saving_characters_the_encoding_lacks_fails() {
+    let path = std::env::temp_dir().join(format!("kite-unmappable-{}.txt", std::process::id()));
+    let mut editor = editor_with("漢字\n");
+    editor.doc.encoding = FileEncoding { encoding: encoding_rs::WINDOWS_1252, bom: false };
+    let error = editor.save(path.to_str().unwrap()).unwrap_err();
+    assert_eq!(error.to_string(), "the text has characters windows-1252 cannot encode");
+    assert!(!path.exists());
+}
+
+#[test]
+fn substitute_takes_vim_group_references() {
+    let cases = [
+        (r"%s/(\w+)=(\w+)/\2=\1/", "b=a\n$1 & cost"),
+        (r"%s/cost/[&] \0/", "a=b\n$1 & [cost] cost"),
+        (r"%s/\$1 \&/\\ $2 $$ \&/", "a=b\n\\ $2 $$ & cost"),
+        (r"%s&\&&+\&+&", "a=b\n$1 +&+ cost"),
+    ];
+    for (command, expected) in cases {
+        let mut editor = editor_with("a=b\n$1 & cost");
+        editor.execute_command(command).unwrap();
+        assert_eq!(buffer_text(&editor), expected, "{}", command);
+    }
+}
+
+// What drawing sends to the terminal, only the cells that changed.
+fn draw_frame(editor: &mut Editor) -> String {
+    let mut out = Vec::new();
+    editor.draw(&mut out, (0, 0), (60, 6));
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn drawn(editor: &mut Editor) -> String {
+    editor.screen.invalidate();
+    draw_frame(editor)
+}
+
+#[test]
+fn the_title_shows_the_file_format_of_a_single_window() {
+    let mut editor = editor_with("ab");
+    assert!(drawn(&mut editor).contains("[UTF-8 unix]"));
+    editor.execute_command("set fileformat=dos").unwrap();
+    assert!(drawn(&mut editor).contains("[UTF-8 dos]"));
+}
+
+// An empty folder of its own for a test.
+fn temp_folder(name: &str) -> std::path::PathBuf {
+    let folder = std::env::temp_dir().join(format!("kite-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&folder).ok();
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+#[test]
+fn undo_history_round_trips_through_a_private_cache_file() {
+    let cache = temp_folder("undo").join("undo").join("a.txt");
+    let mut editor = editor_with("ab");
+    type_text(&mut editor, "x");
+    editor.doc.history.mark_saved(b"xab");
+    editor.doc.history.store_to(&cache).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&cache), 0o600);
+        assert_eq!(mode(cache.parent().unwrap()), 0o700);
+    }
+
+    let mut editor = editor_with("xab");
+    editor.doc.history = History::load_from(&cache, b"xab").unwrap();
+    normal_keys(&mut editor, "u");
+    assert_eq!(buffer_text(&editor), "ab");
+    assert!(History::load_from(&cache, b"xabc").is_none());
+}
+
+#[test]
+fn oversized_or_inconsistent_undo_caches_are_rejected() {
+    let folder = temp_folder("bad-undo");
+    let mut history = History::default();
+    history.mark_saved(b"ab");
+    let cache = folder.join("good");
+    history.store_to(&cache).unwrap();
+    let mut bytes = std::fs::read(&cache).unwrap();
+    assert!(History::load_from(&cache, b"ab").is_some());
+
+    let oversized = folder.join("oversized");
+    std::fs::copy(&cache, &oversized).unwrap();
+    std::fs::OpenOptions::new().write(true).open(&oversized).unwrap().set_len(65 << 20).unwrap();
+    assert!(History::load_from(&oversized, b"ab").is_none());
+
+    // With fixint encoding the root's children length follows the node count
+    // and its parent, a root that is its own child is no tree.
+    bytes[16..24].copy_from_slice(&1u64.to_le_bytes());
+    bytes.splice(24..24, 0u64.to_le_bytes());
+    let inconsistent = folder.join("inconsistent");
+    std::fs::write(&inconsistent, bytes).unwrap();
+    assert!(History::load_from(&inconsistent, b"ab").is_none());
+}
+
+#[test]
+fn undoing_back_to_an_empty_new_document_leaves_nothing_to_save() {
+    let missing = temp_folder("new").join("missing.txt");
+    for doc in [Document::untitled(), Document::open(missing.to_string_lossy().into_owned(), None)] {
+        let mut editor = Editor { doc, ..Editor::default() };
+        editor.set_text_size((12, 4));
+        type_text(&mut editor, "a");
+        assert!(editor.unsaved_document().is_some());
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(buffer_text(&editor), "");
+        assert!(editor.unsaved_document().is_none());
+    }
+}
+
+#[test]
+fn cancelling_a_search_keeps_the_previous_pattern() {
+    let mut editor = editor_with("ab ab ab");
+    normal_keys(&mut editor, "/b");
+    press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+    assert_eq!(editor.cursor_position(), Position::new(0, 1));
+    normal_keys(&mut editor, "/x");
+    press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+    normal_keys(&mut editor, "n");
+    assert_eq!(editor.cursor_position(), Position::new(0, 4));
+}
+
+// Styles of every 16th line once the worker highlighted them all, each drawn
+// on its own from the states kept before it.
+fn settled_styles(editor: &mut Editor) -> Vec<super::highlight::LineStyles> {
+    // The states are syntect's.
+    editor.tree_sitter_languages.clear();
+    let last_line = editor.doc.buffer.len_lines() - 1;
+    let started = std::time::Instant::now();
+    loop {
+        editor.prepare_highlighting();
+        if editor.line_styles(last_line..last_line + 1)[0].is_some() {
+            return (0..=last_line).step_by(16).map(|line| editor.line_styles(line..line + 1).remove(0)).collect();
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(30), "highlighting never finished");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn highlighting_after_edits_matches_highlighting_from_scratch() {
+    let text = (0..600).map(|n| format!("let x{} = \"{}\"; // {}", n, n, n)).collect::<Vec<_>>().join("\n");
+    let mut editor = editor_for("a.rs", &text, Position::new(0, 0));
+    editor.theme_name = "base16-ocean.dark".to_string();
+    let original = settled_styles(&mut editor);
+    type Edit = (&'static str, fn(&mut Editor));
+    let edits: [Edit; 5] = [
+        ("a comment opened", |editor| editor.insert_text((10, 0), "/*", EditKind::Other)),
+        ("the comment closed", |editor| editor.insert_text((300, 0), "*/", EditKind::Other)),
+        ("lines inserted", |editor| editor.insert_text((120, 3), "\n\n\"\n", EditKind::Other)),
+        ("lines removed", |editor| editor.replace_text((200, 0), (280, 0), "", EditKind::Other)),
+        // The second edit comes before the worker is back to the states
+        // after the first.
+        ("a comment opened and a word typed above it", |editor| {
+            editor.insert_text((400, 0), "/*", EditKind::Other);
+            editor.prepare_highlighting();
+            editor.insert_text((60, 0), "x", EditKind::Other);
+        }),
+    ];
+    for (edit, apply) in edits {
+        apply(&mut editor);
+        let mut fresh = editor_for("a.rs", &buffer_text(&editor), Position::new(0, 0));
+        fresh.theme_name = editor.theme_name.clone();
+        assert!(settled_styles(&mut editor) == settled_styles(&mut fresh), "after {}", edit);
+    }
+    for _ in 0..6 {
+        editor.undo();
+    }
+    assert!(settled_styles(&mut editor) == original);
+}
+
+#[test]
+fn cache_names_of_different_paths_differ() {
+    use super::history::path_key;
+    use std::path::{MAIN_SEPARATOR, Path};
+    let slashed = format!("{0}a{0}b{0}c", MAIN_SEPARATOR);
+    let escaped = format!("{0}a{0}b%2Fc", MAIN_SEPARATOR);
+    assert_eq!(path_key(Path::new(&slashed)), "%2Fa%2Fb%2Fc");
+    assert_eq!(path_key(Path::new(&escaped)), "%2Fa%2Fb%252Fc");
+    assert_eq!(path_key(Path::new("100%")), "100%25");
+}
+
+#[test]
+fn ctrl_l_draws_every_cell_again() {
+    let mut editor = editor_with("hello");
+    assert!(draw_frame(&mut editor).contains("hello"));
+    assert!(!draw_frame(&mut editor).contains("hello"));
+    press(&mut editor, KeyCode::Char('l'), KeyModifiers::CONTROL);
+    assert!(draw_frame(&mut editor).contains("hello"));
+    normal_keys(&mut editor, "");
+    draw_frame(&mut editor);
+    press(&mut editor, KeyCode::Char('l'), KeyModifiers::CONTROL);
+    assert!(draw_frame(&mut editor).contains("hello"));
+}
+
+#[test]
+fn soft_wrap_breaks_rows_at_blanks_with_linebreak() {
+    let mut editor = editor_with("one two three four\nx");
+    editor.set_text_size((10, 4));
+    editor.execute_command("set wrap").unwrap();
+    assert_eq!(editor.wrap_starts(0), [0, 10]);
+    editor.execute_command("set linebreak").unwrap();
+    assert_eq!(editor.wrap_starts(0), [0, 8, 14]);
+    let rows: Vec<_> = editor.screen_rows().iter().map(|row| (row.line_index, row.row, row.start, row.end)).collect();
+    assert_eq!(rows, [(0, 0, 0, 8), (0, 1, 8, 14), (0, 2, 14, 19), (1, 0, 0, 2)]);
+}
+
+#[test]
+fn up_and_down_move_by_screen_row_with_soft_wrap() {
+    let mut editor = editor_with("one two three four\nx");
+    editor.set_text_size((10, 4));
+    editor.execute_command("set wrap").unwrap();
+    editor.execute_command("set linebreak").unwrap();
+    editor.set_cursor_position(Position::new(0, 1));
+    let mut visited = Vec::new();
+    for code in [KeyCode::Down, KeyCode::Down, KeyCode::Down, KeyCode::Up, KeyCode::Up] {
+        press(&mut editor, code, KeyModifiers::NONE);
+        visited.push(editor.cursor_position());
+    }
+    let at = |line, grapheme| Position::new(line, grapheme);
+    assert_eq!(visited, [at(0, 9), at(0, 15), at(1, 1), at(0, 15), at(0, 9)]);
+}
+
+#[test]
+fn files_that_cannot_be_saved_open_read_only() {
+    use super::document::OpenStatus;
+    let folder = temp_folder("open");
+    let open = |path: &std::path::Path| Document::open(path.to_string_lossy().into_owned(), None);
+
+    let text = folder.join("text.txt");
+    std::fs::write(&text, "ab\n").unwrap();
+    let document = open(&text);
+    assert_eq!((&document.status, document.read_only), (&OpenStatus::Loaded, false));
+    assert!(super::save::can_write(&text));
+
+    let binary = folder.join("binary.bin");
+    std::fs::write(&binary, b"ab\0cd").unwrap();
+    let document = open(&binary);
+    assert_eq!((&document.status, document.read_only), (&OpenStatus::Binary, true));
+
+    let document = open(&folder);
+    assert!(matches!(document.status, OpenStatus::Unreadable(_)) && document.read_only);
+
+    let document = open(&folder.join("missing.txt"));
+    assert_eq!((&document.status, document.read_only), (&OpenStatus::New, false));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&text, std::fs::Permissions::from_mode(0o444)).unwrap();
+        // Root writes whatever the mode says.
+        let writable = std::fs::OpenOptions::new().append(true).open(&text).is_ok();
+        assert_eq!(super::save::can_write(&text), writable);
+        assert_eq!(open(&text).read_only, !writable);
+    }
+}
+
+#[test]
+fn read_only_documents_are_not_saved() {
+    let folder = temp_folder("read-only");
+    let path = folder.join("a.txt");
+    std::fs::write(&path, "ab\n").unwrap();
+    let mut editor = editor_with("");
+    editor.doc = Document::open(path.to_string_lossy().into_owned(), None);
+    editor.execute_command("set readonly").unwrap();
+    type_text(&mut editor, "x");
+    let error = editor.save(path.to_str().unwrap()).unwrap_err();
+    assert_eq!(error.to_string(), "the file is read-only, :set noreadonly to write it anyway");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "ab\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn saving_replaces_the_file_and_keeps_its_mode() {
+    use std::os::unix::fs::PermissionsExt;
+    let folder = temp_folder("save");
+    let path = folder.join("a.txt");
+    std::fs::write(&path, "old").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+    let link = folder.join("link.txt");
+    std::os::unix::fs::symlink(&path, &link).unwrap();
+
+    super::save::write_atomically(&link, b"new").unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+    assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    // No temporary file is left behind.
+    assert_eq!(std::fs::read_dir(&folder).unwrap().count(), 2);
+}
+
+#[test]
+fn backups_copy_the_file_before_it_is_overwritten() {
+    use super::save;
+    let folder = temp_folder("backup");
+    let path = folder.join("a.txt");
+    std::fs::write(&path, "old").unwrap();
+    save::back_up(&path, "").unwrap();
+    assert_eq!(std::fs::read_to_string(folder.join("a.txt~")).unwrap(), "old");
+
+    let backups = folder.join("backups");
+    save::back_up(&path, backups.to_str().unwrap()).unwrap();
+    let key = super::history::path_key(&std::fs::canonicalize(&path).unwrap());
+    assert_eq!(std::fs::read_to_string(backups.join(format!("{}~", key))).unwrap(), "old");
+    // A file not written yet has nothing to back up.
+    save::back_up(&folder.join("missing.txt"), "").unwrap();
+    assert!(!folder.join("missing.txt~").exists());
+}
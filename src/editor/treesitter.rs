@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use syntect::highlighting::Style;
+use syntect::parsing::Scope;
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+
+use syntect::parsing::SyntaxSet;
+
+use super::buffer::TextBuffer;
+use super::highlight::{HighlightContext, HighlightWorker, Highlighter, Job, LineStyles, TextEdit};
+
+// A grammar compiled in and the file extensions it is used for.
+pub struct Grammar {
+    pub name: &'static str,
+    extensions: &'static [&'static str],
+    language: fn() -> Language,
+    // Highlight queries, earlier patterns win over later ones.
+    queries: &'static [&'static str],
+}
+
+pub const GRAMMARS: &[Grammar] = &[
+    Grammar {
+        name: "rust",
+        extensions: &["rs"],
+        language: || tree_sitter_rust::LANGUAGE.into(),
+        queries: &[tree_sitter_rust::HIGHLIGHTS_QUERY],
+    },
+    Grammar {
+        name: "typescript",
+        extensions: &["ts", "mts", "cts"],
+        language: || tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+        queries: &[tree_sitter_typescript::HIGHLIGHTS_QUERY, tree_sitter_javascript::HIGHLIGHT_QUERY],
+    },
+    Grammar {
+        name: "tsx",
+        extensions: &["tsx"],
+        language: || tree_sitter_typescript::LANGUAGE_TSX.into(),
+        queries: &[tree_sitter_typescript::HIGHLIGHTS_QUERY, tree_sitter_javascript::JSX_HIGHLIGHT_QUERY, tree_sitter_javascript::HIGHLIGHT_QUERY],
+    },
+];
+
+pub fn grammar_for(path: &str) -> Option<&'static Grammar> {
+    let extension = std::path::Path::new(path).extension()?.to_str()?;
+    GRAMMARS.iter().find(|grammar| grammar.extensions.contains(&extension))
+}
+
+// Theme scopes standing in for the capture names of the queries.
+const CAPTURE_SCOPES: &[(&str, &str)] = &[
+    ("attribute", "entity.other.attribute-name"),
+    ("comment", "comment"),
+    ("constant", "constant"),
+    ("constant.builtin", "constant.language"),
+    ("constructor", "entity.name.type"),
+    ("escape", "constant.character.escape"),
+    ("function", "entity.name.function"),
+    ("function.builtin", "support.function"),
+    ("function.macro", "support.function"),
+    ("keyword", "keyword"),
+    ("label", "entity.name.label"),
+    ("number", "constant.numeric"),
+    ("operator", "keyword.operator"),
+    ("property", "variable.other.member"),
+    ("punctuation", "punctuation"),
+    ("string", "string"),
+    ("string.special", "string.regexp"),
+    ("type", "entity.name.type"),
+    ("type.builtin", "storage.type"),
+    ("variable.builtin", "variable.language"),
+    ("variable.parameter", "variable.parameter"),
+];
+
+// Scope for `capture_name` or the nearest of its parents that has one, e.g.
+// `function.method` falls back on `function`.
+fn scope_for(capture_name: &str) -> Option<Scope> {
+    let mut name = capture_name;
+    loop {
+        if let Some((_, scope)) = CAPTURE_SCOPES.iter().find(|(capture, _)| *capture == name) {
+            return Scope::new(scope).ok();
+        }
+        name = &name[..name.rfind('.')?];
+    }
+}
+
+// The syntax tree of a document, parsed again by the worker thread after
+// edits while drawing goes on with the edited old one.
+#[derive(Default)]
+struct ParsedTree {
+    tree: Option<Tree>,
+    // Bumped by every edit, so a parse of an older text is dropped.
+    generation: u64,
+    // The tree was parsed from the text of the current generation.
+    parsed: bool,
+    // Generation the worker was last sent a job for.
+    requested: Option<u64>,
+}
+
+// Parsing a snapshot of the text, reusing the unchanged parts of the old tree.
+struct ParseJob {
+    parsed: Arc<Mutex<ParsedTree>>,
+    generation: u64,
+    buffer: TextBuffer,
+    old_tree: Option<Tree>,
+    grammar: &'static Grammar,
+}
+
+impl Job for ParseJob {
+    fn step(&mut self, _syntax_set: &SyntaxSet) -> Option<bool> {
+        // Nobody else holds the tree once its document is closed.
+        if Arc::strong_count(&self.parsed) == 1 {
+            return None;
+        }
+        let mut parser = Parser::new();
+        parser.set_language(&(self.grammar.language)()).ok()?;
+        let buffer = &self.buffer;
+        let mut read = |byte: usize, _: Point| buffer.chunk_at_byte(byte).as_bytes();
+        let tree = parser.parse_with_options(&mut read, self.old_tree.as_ref(), None)?;
+        let mut parsed = self.parsed.lock().unwrap();
+        if parsed.generation != self.generation {
+            return None;
+        }
+        parsed.tree = Some(tree);
+        parsed.parsed = true;
+        Some(false)
+    }
+
+    fn document(&self) -> usize {
+        Arc::as_ptr(&self.parsed) as usize
+    }
+}
+
+// Highlighting from a syntax tree that the worker thread parses again
+// incrementally after edits.
+pub struct TreeSitterHighlighter {
+    grammar: &'static Grammar,
+    query: Query,
+    // Scope of each capture of the query.
+    scopes: Vec<Option<Scope>>,
+    parsed: Arc<Mutex<ParsedTree>>,
+}
+
+impl std::fmt::Debug for TreeSitterHighlighter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TreeSitterHighlighter").field("grammar", &self.grammar.name).finish()
+    }
+}
+
+impl TreeSitterHighlighter {
+    pub fn new(name: &str) -> Option<Self> {
+        let grammar = GRAMMARS.iter().find(|grammar| grammar.name == name)?;
+        let language = (grammar.language)();
+        Parser::new().set_language(&language).ok()?;
+        let query = Query::new(&language, &grammar.queries.concat()).ok()?;
+        let scopes = query.capture_names().iter().map(|name| scope_for(name)).collect();
+        Some(Self { grammar, query, scopes, parsed: Arc::default() })
+    }
+}
+
+impl Highlighter for TreeSitterHighlighter {
+    fn language(&self) -> Option<&'static str> {
+        Some(self.grammar.name)
+    }
+
+    // Shifts the old tree along with the text until the new one is parsed.
+    fn edit(&mut self, edit: &TextEdit) {
+        let mut parsed = self.parsed.lock().unwrap();
+        if let Some(tree) = &mut parsed.tree {
+            let point = |(row, column): (usize, usize)| Point { row, column };
+            tree.edit(&InputEdit {
+                start_byte: edit.start_byte,
+                old_end_byte: edit.old_end_byte,
+                new_end_byte: edit.new_end_byte,
+                start_position: point(edit.start),
+                old_end_position: point(edit.old_end),
+                new_end_position: point(edit.new_end),
+            });
+        }
+        parsed.generation += 1;
+        parsed.parsed = false;
+    }
+
+    // Hands the text to the worker unless the tree is up to date or already
+    // being parsed.
+    fn prepare(&mut self, context: &HighlightContext, worker: &mut HighlightWorker) {
+        let mut parsed = self.parsed.lock().unwrap();
+        if parsed.parsed || parsed.requested == Some(parsed.generation) {
+            return;
+        }
+        parsed.requested = Some(parsed.generation);
+        let job = ParseJob {
+            parsed: self.parsed.clone(),
+            generation: parsed.generation,
+            buffer: context.buffer.clone(),
+            old_tree: parsed.tree.clone(),
+            grammar: self.grammar,
+        };
+        drop(parsed);
+        worker.send(Box::new(job), context.syntax_set);
+    }
+
+    fn highlight_lines(&self, context: &HighlightContext, lines: Range<usize>) -> Vec<LineStyles> {
+        // Until the first parse is done there is nothing to go by.
+        let tree = match self.parsed.lock().unwrap().tree.clone() {
+            Some(tree) => tree,
+            None => return vec![None; lines.len()],
+        };
+        let buffer = context.buffer;
+        let highlighter = syntect::highlighting::Highlighter::new(context.theme);
+        let plain = highlighter.get_default();
+        let styles: Vec<Style> = self.scopes.iter()
+            .map(|scope| scope.map_or(plain, |scope| highlighter.style_for_stack(&[scope])))
+            .collect();
+
+        let last_line = lines.end.min(buffer.len_lines());
+        let start_byte = buffer.byte_offset((lines.start.min(last_line), 0));
+        let end_byte = if last_line < buffer.len_lines() {buffer.byte_offset((last_line, 0))} else {buffer.len_bytes()};
+
+        // Capture painted over each byte of the lines.
+        let mut painted: Vec<Option<usize>> = vec![None; end_byte - start_byte];
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(start_byte..end_byte);
+        let text = |node: Node| std::iter::once(buffer.text_in_bytes(node.byte_range()).into_owned());
+        let mut captures = cursor.captures(&self.query, tree.root_node(), text);
+        // As with tree-sitter's own highlighter the first pattern to capture
+        // a node decides its style, nodes nested in it are painted over it.
+        let mut captured = HashSet::new();
+        while let Some((found, index)) = captures.next() {
+            let capture = found.captures[*index];
+            if self.scopes[capture.index as usize].is_none() || !captured.insert(capture.node.id()) {
+                continue;
+            }
+            let range = capture.node.byte_range();
+            for byte in range.start.max(start_byte)..range.end.min(end_byte) {
+                painted[byte - start_byte] = Some(capture.index as usize);
+            }
+        }
+
+        lines.map(|line_index| {
+            let line = match buffer.line(line_index) {
+                Some(line) => line,
+                None => return Some(Vec::new()),
+            };
+            let line_start = buffer.byte_offset((line_index, 0)) - start_byte;
+            let mut ranges: Vec<(Style, Range<usize>)> = Vec::new();
+            for byte in 0..line.len() {
+                let style = painted[line_start + byte].map_or(plain, |capture| styles[capture]);
+                match ranges.last_mut() {
+                    Some((last_style, range)) if *last_style == style => range.end = byte + 1,
+                    _ => ranges.push((style, byte..byte + 1)),
+                }
+            }
+            Some(ranges)
+        }).collect()
+    }
+}
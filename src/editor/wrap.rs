@@ -0,0 +1,170 @@
+use super::Editor;
+use super::position::Position;
+
+// Part of a line shown on one row of the text area, `start..end` are the
+// columns it shows. With soft wrap off every row is a whole line cut to the
+// horizontal scroll.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenRow {
+    pub line_index: usize,
+    // Which row of the line this is, continuation rows are above 0.
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Editor {
+
+    // Graphemes the rows of `line_index` start at when it is wrapped to the
+    // text width. The cursor may sit one past the end of a line, so that
+    // needs a cell too. Lines past the end of the buffer take one row.
+    pub (super) fn wrap_starts(&self, line_index: usize) -> Vec<usize> {
+        let line = self.line_at_index(line_index).unwrap_or_default();
+        let (graphemes, mut widths): (Vec<&str>, Vec<usize>) = self.grapheme_widths(&line).unzip();
+        widths.push(1);
+        let width = self.text_size.0.max(1);
+        let mut starts = vec![0];
+        let mut start = 0;
+        loop {
+            // Every row takes at least one grapheme, even one too wide for it.
+            let mut next = start + 1;
+            let mut used = widths[start];
+            while next < widths.len() && used + widths[next] <= width {
+                used += widths[next];
+                next += 1;
+            }
+            if next == widths.len() {
+                break;
+            }
+            if self.wrap_at_words {
+                // Break after the last blank that still fits.
+                if let Some(after_blank) = (start + 1..=next).rev().find(|i| graphemes[i - 1].trim().is_empty()) {
+                    next = after_blank;
+                }
+            }
+            starts.push(next);
+            start = next;
+        }
+        starts
+    }
+
+    // Row of the line holding the grapheme at `index`.
+    fn wrap_row(starts: &[usize], index: usize) -> usize {
+        starts.iter().rposition(|start| *start <= index).unwrap_or(0)
+    }
+
+    fn wrap_row_count(&self, line_index: usize) -> usize {
+        self.wrap_starts(line_index).len()
+    }
+
+    // Rows filling the text area from the top of the view down.
+    pub (super) fn screen_rows(&self) -> Vec<ScreenRow> {
+        let top = self.view.display_top_line_index;
+        if !self.soft_wrap {
+            let start = self.view.display_left_column;
+            return (top..top + self.text_size.1)
+                .map(|line_index| ScreenRow { line_index, row: 0, start, end: start + self.text_size.0 })
+                .collect();
+        }
+        let mut rows = Vec::new();
+        let mut line_index = top;
+        let mut first_row = self.view.display_top_row;
+        while rows.len() < self.text_size.1 {
+            let starts = self.wrap_starts(line_index);
+            let end_of_line = self.line_grapheme_count(line_index) + 1;
+            for row in first_row.min(starts.len() - 1)..starts.len() {
+                let end = starts.get(row + 1).copied().unwrap_or(end_of_line);
+                rows.push(ScreenRow {
+                    line_index,
+                    row,
+                    start: self.column_of(Position::new(line_index, starts[row])),
+                    end: self.column_of(Position::new(line_index, end)),
+                });
+            }
+            first_row = 0;
+            line_index += 1;
+        }
+        rows.truncate(self.text_size.1);
+        rows
+    }
+
+    // Scrolls so the row holding the cursor is on screen.
+    pub (super) fn clamp_wrapped_display_to_cursor(&mut self) {
+        self.view.display_left_column = 0;
+        let height = self.text_size.1.max(1);
+        let cursor_line = self.view.cursor.line;
+        let cursor = (cursor_line, Self::wrap_row(&self.wrap_starts(cursor_line), self.view.cursor.grapheme));
+        let top_rows = self.wrap_row_count(self.view.display_top_line_index);
+        self.view.display_top_row = self.view.display_top_row.min(top_rows - 1);
+        if cursor <= (self.view.display_top_line_index, self.view.display_top_row) {
+            (self.view.display_top_line_index, self.view.display_top_row) = cursor;
+            return;
+        }
+        // Far below every line takes at least a row, so start close by.
+        if cursor_line >= self.view.display_top_line_index + height {
+            self.view.display_top_line_index = cursor_line + 1 - height;
+            self.view.display_top_row = 0;
+        }
+        let mut rows = cursor.1 + 1;
+        for line_index in self.view.display_top_line_index..cursor_line {
+            rows += self.wrap_row_count(line_index);
+        }
+        rows -= self.view.display_top_row;
+        while rows > height {
+            self.scroll_wrapped_down();
+            rows -= 1;
+        }
+    }
+
+    pub (super) fn scroll_wrapped_down(&mut self) {
+        if self.view.display_top_row + 1 < self.wrap_row_count(self.view.display_top_line_index) {
+            self.view.display_top_row += 1;
+        } else {
+            self.view.display_top_line_index += 1;
+            self.view.display_top_row = 0;
+        }
+    }
+
+    pub (super) fn scroll_wrapped_up(&mut self) {
+        if self.view.display_top_row > 0 {
+            self.view.display_top_row -= 1;
+        } else if self.view.display_top_line_index > 0 {
+            self.view.display_top_line_index -= 1;
+            self.view.display_top_row = self.wrap_row_count(self.view.display_top_line_index) - 1;
+        }
+    }
+
+    // Up or down a screen row within wrapped lines, keeping to the column
+    // the cursor was in before the first move.
+    pub (super) fn move_cursor_row(&mut self, down: bool) {
+        let starts = self.wrap_starts(self.view.cursor.line);
+        let row = Self::wrap_row(&starts, self.view.cursor.grapheme);
+        let row_column = self.cursor_column() - self.column_of(Position::new(self.view.cursor.line, starts[row]));
+        let column = *self.view.cursor_prefered_row_column.get_or_insert(row_column);
+        let (line_index, row) = if down {
+            if row + 1 < starts.len() {
+                (self.view.cursor.line, row + 1)
+            } else if self.view.cursor.line + 1 < self.doc.buffer.len_lines() {
+                (self.view.cursor.line + 1, 0)
+            } else {
+                return;
+            }
+        } else if row > 0 {
+            (self.view.cursor.line, row - 1)
+        } else if self.view.cursor.line > 0 {
+            (self.view.cursor.line - 1, self.wrap_row_count(self.view.cursor.line - 1) - 1)
+        } else {
+            return;
+        };
+        let starts = self.wrap_starts(line_index);
+        let last_on_row = match starts.get(row + 1) {
+            Some(next) => next - 1,
+            None => self.line_grapheme_count(line_index),
+        };
+        let target = self.position_at_column(line_index, self.column_of(Position::new(line_index, starts[row])) + column);
+        self.view.cursor = Position::new(line_index, target.grapheme.clamp(starts[row], last_on_row));
+        self.view.cursor_prefered_column = self.cursor_column();
+        if self.display_shifted_by_cursor {self.clamp_display_to_cursor();}
+    }
+
+}
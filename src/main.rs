@@ -1,5 +1,3 @@
-#![feature(iter_intersperse)]
-
 mod editor;
 use editor::*;
 
@@ -13,32 +11,63 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    /// Files to open, each in a buffer of its own
     #[clap(value_parser)]
-    file_name: String,
+    file_names: Vec<String>,
 
-    #[clap(value_parser)]
+    /// Color theme, e.g. "base16-ocean.dark" (no longer taken after the file names)
+    #[clap(long, value_parser)]
     theme: Option<String>,
 
-    #[clap(value_parser)]
+    /// Folder of .tmTheme files to choose the theme from as well
+    #[clap(long, value_parser)]
     extra_themes_folder: Option<String>,
+
+    /// Also copy to the system clipboard through the terminal (OSC 52)
+    #[clap(long)]
+    osc52: bool,
+
+    /// Read files in this encoding (e.g. latin1, utf-16le) instead of guessing it
+    #[clap(long, value_parser)]
+    encoding: Option<String>,
 }
 
 enum RenderThreadMsg {
+    Redraw,
     Halt,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let mut editor = Editor::new(args.file_name.clone());
+    let encoding = match args.encoding {
+        Some(label) => Some(encoding_rs::Encoding::for_label(label.as_bytes()).ok_or(format!("Unknown encoding: {}", label))?),
+        None => None,
+    };
+
+    // The theme used to follow the file name, such a name is not opened as a
+    // file by mistake.
+    if args.theme.is_none() && args.file_names.len() > 1 {
+        let themes = syntect::highlighting::ThemeSet::load_defaults().themes;
+        if let Some(theme) = args.file_names[1..].iter().find(|name| themes.contains_key(*name) && !std::path::Path::new(name).exists()) {
+            return Err(format!("{} is a theme, pass it with --theme {}", theme, theme).into());
+        }
+    }
+
+    let mut editor = Editor::new(args.file_names, encoding);
     editor.theme_name = if let Some(theme) = args.theme {theme.clone()} else {"Solarized (dark)".to_string()};
     if let Some(theme_folder) = args.extra_themes_folder {editor.theme_set.add_from_folder(theme_folder).unwrap();}
+    editor.clipboard.use_osc52 = args.osc52;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    {
+        let tx = tx.clone();
+        editor.set_redraw_notifier(move || {tx.send(RenderThreadMsg::Redraw).ok();});
+    }
 
     let editor = Mutex::new(editor);
     let editor = Arc::new(editor);
 
-    let (tx, rx) = std::sync::mpsc::channel();
-
     let render_thread;
     {
         let editor = editor.clone();
@@ -49,17 +78,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 terminal::enable_raw_mode()?;
                 stdout.execute(terminal::EnterAlternateScreen)?;
                 stdout.execute(crossterm::cursor::Hide).unwrap();
-
-                'renderloop: loop {
-                    if let Ok(msg) = rx.try_recv() {
-                        match msg {
-                            RenderThreadMsg::Halt => {break 'renderloop;}
-                        }
+                stdout.execute(event::EnableBracketedPaste)?;
+
+                // Only draws when something may have changed, the screen
+                // then sends just the cells that did.
+                editor.lock().unwrap().draw(&mut stdout, (0,0), terminal::size().unwrap());
+                'renderloop: while let Ok(msg) = rx.recv() {
+                    let mut halt = matches!(msg, RenderThreadMsg::Halt);
+                    while let Ok(msg) = rx.try_recv() {
+                        halt |= matches!(msg, RenderThreadMsg::Halt);
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    if halt {break 'renderloop;}
                     editor.lock().unwrap().draw(&mut stdout, (0,0), terminal::size().unwrap());
                 }
 
+                stdout.execute(event::DisableBracketedPaste)?;
                 stdout.execute(crossterm::cursor::Show).unwrap();
                 stdout.execute(terminal::LeaveAlternateScreen)?;
                 terminal::disable_raw_mode()?;
@@ -71,11 +104,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut running = true;
     while running {
-        
-        if event::poll(std::time::Duration::from_millis(100))? {
-            let event = event::read()?;
-            running = editor.lock().unwrap().process_event(event)?;
-        }
+        let event = event::read()?;
+        running = editor.lock().unwrap().process_event(event)?;
+        tx.send(RenderThreadMsg::Redraw).unwrap();
     }
 
     tx.send(RenderThreadMsg::Halt).unwrap();